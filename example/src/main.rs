@@ -26,7 +26,7 @@ fn main() {
     let recognizer = Recognizer::new(&model_path, vad_params, 3).unwrap();
 
     let f = || recognizer.process(&content).unwrap();
-    let res = timed("VAD", f);
+    let _res = timed("VAD", f);
 
     // res.iter().for_each(|ts| println!("{} - {}", ts.start, ts.end));
 
@@ -0,0 +1,169 @@
+use crate::utils::TimeStamp;
+use std::ops::Range;
+
+/// A span of the compacted audio copied from the original audio. All values are in samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeMapSegment {
+    pub compacted_start: usize,
+    pub original_start: usize,
+    pub len: usize,
+}
+
+/// Converts positions in the compacted audio back to positions in the original audio.
+#[derive(Debug, Clone, Default)]
+pub struct TimeMap {
+    segments: Vec<TimeMapSegment>,
+    sample_rate: usize,
+}
+
+impl TimeMap {
+    pub fn segments(&self) -> &[TimeMapSegment] {
+        &self.segments
+    }
+
+    /// Maps a sample index of the compacted audio to the sample index of the original audio.
+    pub fn to_original(&self, compacted_sample: usize) -> usize {
+        let idx = self
+            .segments
+            .partition_point(|segment| segment.compacted_start <= compacted_sample)
+            .saturating_sub(1);
+        match self.segments.get(idx) {
+            Some(segment) => segment.original_start + (compacted_sample - segment.compacted_start).min(segment.len),
+            None => compacted_sample,
+        }
+    }
+
+    /// Maps a position in seconds of the compacted audio to the position in seconds of the original audio.
+    pub fn to_original_seconds(&self, compacted_seconds: f64) -> f64 {
+        let compacted_sample = (compacted_seconds * self.sample_rate as f64).round() as usize;
+        self.to_original(compacted_sample) as f64 / self.sample_rate as f64
+    }
+}
+
+/// Audio with the non-speech removed or shortened.
+#[derive(Debug, Clone, Default)]
+pub struct Compacted {
+    pub samples: Vec<i16>,
+    pub time_map: TimeMap,
+}
+
+/// Removes non-speech from `samples`, given the `speeches` detected in them.
+///
+/// Silences longer than `max_silence_ms` are shortened to `max_silence_ms` by keeping their edges adjacent to the
+/// speech, so `max_silence_ms = 0` removes everything but speech.
+pub fn compact(samples: &[i16], speeches: &[TimeStamp], sample_rate: usize, max_silence_ms: usize) -> Compacted {
+    let max_silence = max_silence_ms * sample_rate / 1000;
+    let to_sample = |seconds: f64| ((seconds * sample_rate as f64).round() as usize).min(samples.len());
+
+    let mut kept: Vec<Range<usize>> = Vec::new();
+    let mut keep = |range: Range<usize>| {
+        if range.is_empty() {
+            return;
+        }
+        match kept.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => kept.push(range),
+        }
+    };
+
+    let mut silence_start = 0;
+    for (idx, speech) in speeches.iter().enumerate() {
+        let (start, end) = (to_sample(speech.start), to_sample(speech.end));
+        if start < silence_start {
+            // overlapping speeches
+            keep(silence_start..end.max(silence_start));
+            silence_start = silence_start.max(end);
+            continue;
+        }
+        if idx == 0 {
+            // leading silence keeps its end only
+            keep(start.saturating_sub(max_silence)..start);
+        } else if start - silence_start > max_silence {
+            keep(silence_start..silence_start + max_silence / 2);
+            keep(start - (max_silence - max_silence / 2)..start);
+        } else {
+            keep(silence_start..start);
+        }
+        keep(start..end);
+        silence_start = end;
+    }
+    // trailing silence keeps its beginning only
+    keep(silence_start..(silence_start + max_silence).min(samples.len()));
+
+    let mut compacted = Compacted {
+        samples: Vec::with_capacity(kept.iter().map(|range| range.len()).sum()),
+        time_map: TimeMap {
+            segments: Vec::with_capacity(kept.len()),
+            sample_rate,
+        },
+    };
+    for range in kept {
+        compacted.time_map.segments.push(TimeMapSegment {
+            compacted_start: compacted.samples.len(),
+            original_start: range.start,
+            len: range.len(),
+        });
+        compacted.samples.extend_from_slice(&samples[range]);
+    }
+    compacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speeches() -> Vec<TimeStamp> {
+        vec![
+            TimeStamp {
+                start: 1.0,
+                end: 2.0,
+                samples: None,
+                stats: None,
+            },
+            TimeStamp {
+                start: 5.0,
+                end: 5.5,
+                samples: None,
+                stats: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_compact_removes_non_speech() {
+        let samples: Vec<i16> = (0..8000).map(|x| x as i16).collect();
+        let compacted = compact(&samples, &speeches(), 1000, 0);
+
+        assert_eq!(compacted.samples.len(), 1500);
+        assert_eq!(compacted.samples[0], 1000);
+        assert_eq!(compacted.samples[1000], 5000);
+        assert_eq!(compacted.time_map.to_original(0), 1000);
+        assert_eq!(compacted.time_map.to_original(999), 1999);
+        assert_eq!(compacted.time_map.to_original(1000), 5000);
+        assert_eq!(compacted.time_map.to_original_seconds(1.25), 5.25);
+    }
+
+    #[test]
+    fn test_compact_shortens_silence() {
+        let samples: Vec<i16> = (0..8000).map(|x| x as i16).collect();
+        let compacted = compact(&samples, &speeches(), 1000, 400);
+
+        // 400 leading + 1000 speech + 400 gap + 500 speech + 400 trailing
+        assert_eq!(compacted.samples.len(), 2700);
+        assert_eq!(compacted.samples[0], 600);
+        assert_eq!(compacted.time_map.to_original(400), 1000);
+        assert_eq!(compacted.time_map.to_original(1400), 2000);
+        assert_eq!(compacted.time_map.to_original(1600), 4800);
+        assert_eq!(compacted.time_map.to_original(1800), 5000);
+        assert_eq!(compacted.time_map.segments().len(), 2);
+    }
+
+    #[test]
+    fn test_compact_keeps_short_silence() {
+        let samples = vec![0i16; 8000];
+        let compacted = compact(&samples, &speeches(), 1000, 10_000);
+
+        assert_eq!(compacted.samples.len(), samples.len());
+        assert_eq!(compacted.time_map.segments().len(), 1);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::diarization::SpeakerHints;
+
+/// Agglomerative clustering with average linkage over cosine similarity. Clusters are merged while the similarity of
+/// the closest pair is at least `threshold`, or while there are more than `hints.max_speakers` clusters, and never
+/// below `hints.min_speakers` clusters.
+///
+/// The clustering takes quadratic memory, so more than `max_embeddings` embeddings are clustered in two stages: the
+/// consecutive blocks of at most `max_embeddings` embeddings are clustered by `threshold` alone, into at most half as many
+/// clusters, and then the mean embeddings of these clusters are clustered as above.
+///
+/// Returns the cluster label of every embedding, labels are numbered in the order of first appearance.
+pub(crate) fn cluster(
+    embeddings: &[Vec<f32>],
+    threshold: f32,
+    hints: SpeakerHints,
+    max_embeddings: usize,
+) -> Vec<usize> {
+    let normalized = embeddings.iter().map(|e| normalize(e)).collect::<Vec<_>>();
+    cluster_weighted(&normalized, &vec![1; normalized.len()], threshold, hints, max_embeddings.max(2))
+}
+
+/// Clusters normalized embeddings, each standing for `sizes` embeddings of the first stage.
+fn cluster_weighted(
+    normalized: &[Vec<f32>],
+    sizes: &[usize],
+    threshold: f32,
+    hints: SpeakerHints,
+    max_embeddings: usize,
+) -> Vec<usize> {
+    if normalized.len() <= max_embeddings {
+        return agglomerate(normalized, sizes, threshold, hints);
+    }
+
+    // blocks of equal lengths, halving a short last block could merge its speakers
+    let block_len = normalized.len().div_ceil(normalized.len().div_ceil(max_embeddings));
+    let mut block_labels = Vec::with_capacity(normalized.len());
+    let mut means = Vec::new();
+    let mut mean_sizes = Vec::new();
+    for (block, block_sizes) in normalized.chunks(block_len).zip(sizes.chunks(block_len)) {
+        let block_hints = SpeakerHints {
+            min_speakers: None,
+            max_speakers: Some(block.len().div_ceil(2)),
+        };
+        let labels = agglomerate(block, block_sizes, threshold, block_hints);
+        let clusters = labels.iter().max().map_or(0, |max| max + 1);
+        let mut sums = vec![vec![0.0f32; block[0].len()]; clusters];
+        let mut totals = vec![0usize; clusters];
+        for ((embedding, &size), &label) in block.iter().zip(block_sizes).zip(&labels) {
+            for (sum, x) in sums[label].iter_mut().zip(embedding) {
+                *sum += size as f32 * x;
+            }
+            totals[label] += size;
+        }
+        block_labels.extend(labels.iter().map(|label| means.len() + label));
+        means.extend(sums.iter().map(|sum| normalize(sum)));
+        mean_sizes.extend(totals);
+    }
+
+    let mean_labels = cluster_weighted(&means, &mean_sizes, threshold, hints, max_embeddings);
+    relabel(block_labels.into_iter().map(|label| mean_labels[label]))
+}
+
+/// Numbers the labels in the order of first appearance.
+fn relabel(labels: impl Iterator<Item = usize>) -> Vec<usize> {
+    let mut numbers = std::collections::HashMap::new();
+    labels
+        .map(|label| {
+            let next = numbers.len();
+            *numbers.entry(label).or_insert(next)
+        })
+        .collect()
+}
+
+fn agglomerate(normalized: &[Vec<f32>], sizes: &[usize], threshold: f32, hints: SpeakerHints) -> Vec<usize> {
+    let n = normalized.len();
+    let mut similarity = vec![vec![0.0f32; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let s = dot(&normalized[i], &normalized[j]);
+            similarity[i][j] = s;
+            similarity[j][i] = s;
+        }
+    }
+
+    let mut active = vec![true; n];
+    let mut sizes = sizes.to_vec();
+    let mut parents = (0..n).collect::<Vec<_>>();
+    let mut nearest = (0..n)
+        .map(|i| nearest_neighbour(&similarity, &active, i))
+        .collect::<Vec<_>>();
+
+    let min_clusters = hints.min_speakers.unwrap_or(1);
+    let max_clusters = hints.max_speakers.unwrap_or(usize::MAX);
+    let mut clusters = n;
+    while let Some((i, (j, s))) = (0..n)
+        .filter(|&i| active[i])
+        .filter_map(|i| nearest[i].map(|nn| (i, nn)))
+        .max_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b))
+    {
+        if clusters <= min_clusters || (s < threshold && clusters <= max_clusters) {
+            break;
+        }
+        // merge j into i, updating the average linkage
+        active[j] = false;
+        parents[j] = i;
+        for k in (0..n).filter(|&k| active[k] && k != i) {
+            let merged = (sizes[i] as f32 * similarity[i][k] + sizes[j] as f32 * similarity[j][k])
+                / (sizes[i] + sizes[j]) as f32;
+            similarity[i][k] = merged;
+            similarity[k][i] = merged;
+        }
+        sizes[i] += sizes[j];
+        clusters -= 1;
+
+        nearest[i] = nearest_neighbour(&similarity, &active, i);
+        for k in (0..n).filter(|&k| active[k] && k != i) {
+            nearest[k] = match nearest[k] {
+                Some((nn, _)) if nn == i || nn == j => nearest_neighbour(&similarity, &active, k),
+                Some((_, s)) if similarity[k][i] > s => Some((i, similarity[k][i])),
+                nn => nn,
+            };
+        }
+    }
+
+    let mut labels = Vec::with_capacity(n);
+    let mut cluster_labels = vec![usize::MAX; n];
+    let mut next_label = 0;
+    for i in 0..n {
+        let mut root = i;
+        while parents[root] != root {
+            root = parents[root];
+        }
+        if cluster_labels[root] == usize::MAX {
+            cluster_labels[root] = next_label;
+            next_label += 1;
+        }
+        labels.push(cluster_labels[root]);
+    }
+    labels
+}
+
+fn nearest_neighbour(similarity: &[Vec<f32>], active: &[bool], i: usize) -> Option<(usize, f32)> {
+    (0..similarity.len())
+        .filter(|&j| j != i && active[j])
+        .map(|j| (j, similarity[i][j]))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalize(embedding: &[f32]) -> Vec<f32> {
+    let norm = dot(embedding, embedding).sqrt().max(f32::EPSILON);
+    embedding.iter().map(|x| x / norm).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_by_threshold() {
+        let embeddings = vec![
+            vec![1.0, 0.1, 0.0],
+            vec![0.0, 1.0, 0.1],
+            vec![0.9, 0.0, 0.1],
+            vec![0.1, 0.9, 0.0],
+            vec![1.0, 0.0, 0.0],
+        ];
+        assert_eq!(cluster(&embeddings, 0.5, SpeakerHints::default(), 100), vec![0, 1, 0, 1, 0]);
+        assert_eq!(cluster(&embeddings, 1.1, SpeakerHints::default(), 100), vec![0, 1, 2, 3, 4]);
+        assert_eq!(cluster(&embeddings, -1.0, SpeakerHints::default(), 100), vec![0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_cluster_with_hints() {
+        let embeddings = vec![
+            vec![1.0, 0.1, 0.0],
+            vec![0.0, 1.0, 0.1],
+            vec![0.9, 0.0, 0.1],
+            vec![0.1, 0.9, 0.0],
+            vec![1.0, 0.0, 0.0],
+        ];
+        assert_eq!(cluster(&embeddings, 1.1, SpeakerHints::exactly(2), 100), vec![0, 1, 0, 1, 0]);
+        assert_eq!(cluster(&embeddings, -1.0, SpeakerHints::exactly(2), 100), vec![0, 1, 0, 1, 0]);
+        let hints = SpeakerHints {
+            min_speakers: Some(3),
+            max_speakers: None,
+        };
+        assert_eq!(cluster(&embeddings, 0.5, hints, 100).iter().max(), Some(&2));
+        let hints = SpeakerHints {
+            min_speakers: None,
+            max_speakers: Some(1),
+        };
+        assert_eq!(cluster(&embeddings, 1.1, hints, 100), vec![0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_cluster_in_two_stages() {
+        // two speakers taking turns of three windows, clustered in blocks of four windows
+        let embeddings = (0..24)
+            .map(|i| {
+                let noise = (i % 5) as f32 * 0.05;
+                if i / 3 % 2 == 0 {
+                    vec![1.0, noise, 0.0]
+                } else {
+                    vec![noise, 1.0, 0.1]
+                }
+            })
+            .collect::<Vec<_>>();
+        let labels = cluster(&embeddings, 0.5, SpeakerHints::default(), 4);
+        assert_eq!(labels, cluster(&embeddings, 0.5, SpeakerHints::default(), 100));
+        assert_eq!(labels, (0..24).map(|i| i / 3 % 2).collect::<Vec<_>>());
+
+        assert_eq!(cluster(&embeddings, 1.1, SpeakerHints::exactly(2), 4), labels);
+        assert_eq!(cluster(&embeddings, -1.0, SpeakerHints::default(), 4), vec![0; 24]);
+        // no merges by threshold, the blocks are still halved until they fit
+        let labels = cluster(&embeddings, 1.1, SpeakerHints::default(), 4);
+        assert!(labels.iter().max() < Some(&4));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::diarization::fbank::Fbank;
+use crate::OnnxSession;
+use ndarray::{Array2, Array3};
+use std::sync::Arc;
+
+const NUM_MEL_BINS: usize = 80;
+
+/// Input expected by the speaker embedding model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingInput {
+    /// 80-bin log mel filterbank features of shape `[1, frames, 80]`, e.g. WeSpeaker exports.
+    Fbank,
+    /// Raw waveform in `[-1, 1]` of shape `[1, samples]`, e.g. ECAPA exports with built-in feature extraction.
+    Waveform,
+}
+
+/// Extracts speaker embeddings with an ONNX model taking either fbank features or the raw waveform.
+pub(crate) struct SpeakerEmbedder {
+    session: Arc<OnnxSession>,
+    input: EmbeddingInput,
+    sample_rate: usize,
+    fbank: Fbank,
+}
+
+impl SpeakerEmbedder {
+    pub fn new(session: Arc<OnnxSession>, input: EmbeddingInput, sample_rate: usize) -> Self {
+        Self {
+            session,
+            input,
+            sample_rate,
+            fbank: Fbank::new(sample_rate, NUM_MEL_BINS),
+        }
+    }
+
+    /// Computes the embedding of `samples` recorded at `sample_rate`. Returns `None` if the audio is too short.
+    pub fn embed(&mut self, samples: &[i16], sample_rate: usize) -> crate::Result<Option<Vec<f32>>> {
+        let samples = resample(samples, sample_rate, self.sample_rate);
+        let input = match self.input {
+            EmbeddingInput::Fbank => {
+                let features = self.fbank.compute(&samples);
+                if features.is_empty() {
+                    return Ok(None);
+                }
+                let frames = features.len();
+                let features = Array3::from_shape_vec([1, frames, NUM_MEL_BINS], features.concat())
+                    .expect("fbank frames have NUM_MEL_BINS bins");
+                features.into_dyn()
+            }
+            EmbeddingInput::Waveform => {
+                if samples.is_empty() {
+                    return Ok(None);
+                }
+                let waveform = samples.iter().map(|x| x / i16::MAX as f32).collect::<Vec<_>>();
+                let waveform = Array2::from_shape_vec([1, waveform.len()], waveform).expect("single row waveform");
+                waveform.into_dyn()
+            }
+        };
+
+        let input_name = self.session.inputs[0].name.as_str();
+        let outputs = self.session.run(ort::inputs![input_name => input]?)?;
+        let (_, embedding) = outputs[0].try_extract_raw_tensor::<f32>()?;
+        Ok(Some(embedding.to_vec()))
+    }
+}
+
+/// Linear interpolation resampling to the sample rate of the embedding model, keeping the `i16` scale.
+fn resample(samples: &[i16], from: usize, to: usize) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.iter().map(|x| *x as f32).collect();
+    }
+    let len = samples.len() * to / from;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * from as f64 / to as f64;
+            let idx = position as usize;
+            let frac = (position - idx as f64) as f32;
+            let current = samples[idx] as f32;
+            let next = *samples.get(idx + 1).unwrap_or(&samples[idx]) as f32;
+            current + (next - current) * frac
+        })
+        .collect()
+}
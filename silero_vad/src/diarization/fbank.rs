@@ -0,0 +1,148 @@
+use realfft::RealFftPlanner;
+
+const FRAME_LENGTH_MS: usize = 25;
+const FRAME_SHIFT_MS: usize = 10;
+const PREEMPHASIS: f32 = 0.97;
+const LOW_FREQ: f32 = 20.0;
+
+/// Kaldi-compatible log mel filterbank features, as expected by the WeSpeaker and most ECAPA exports.
+pub(crate) struct Fbank {
+    frame_length: usize,
+    frame_shift: usize,
+    fft_size: usize,
+    window: Vec<f32>,
+    mel_banks: Vec<Vec<f32>>,
+    planner: RealFftPlanner<f32>,
+}
+
+impl Fbank {
+    pub fn new(sample_rate: usize, num_mel_bins: usize) -> Self {
+        let frame_length = sample_rate * FRAME_LENGTH_MS / 1000;
+        let frame_shift = sample_rate * FRAME_SHIFT_MS / 1000;
+        let fft_size = frame_length.next_power_of_two();
+        // povey window
+        let window = (0..frame_length)
+            .map(|i| {
+                let hann = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (frame_length - 1) as f32).cos();
+                hann.powf(0.85)
+            })
+            .collect();
+        Self {
+            frame_length,
+            frame_shift,
+            fft_size,
+            window,
+            mel_banks: mel_banks(sample_rate, fft_size, num_mel_bins),
+            planner: RealFftPlanner::new(),
+        }
+    }
+
+    /// Computes mean-normalized features of `samples` (in the `i16` scale), one row per 10 ms frame.
+    pub fn compute(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        if samples.len() < self.frame_length {
+            return Vec::new();
+        }
+        let num_frames = 1 + (samples.len() - self.frame_length) / self.frame_shift;
+        let fft = self.planner.plan_fft_forward(self.fft_size);
+        let mut input = fft.make_input_vec();
+        let mut spectrum = fft.make_output_vec();
+
+        let mut features = Vec::with_capacity(num_frames);
+        for frame_idx in 0..num_frames {
+            let frame = &samples[frame_idx * self.frame_shift..frame_idx * self.frame_shift + self.frame_length];
+            let mean = frame.iter().sum::<f32>() / frame.len() as f32;
+
+            input.fill(0.0);
+            input[..self.frame_length]
+                .iter_mut()
+                .zip(frame)
+                .for_each(|(x, sample)| *x = sample - mean);
+            for i in (1..self.frame_length).rev() {
+                input[i] -= PREEMPHASIS * input[i - 1];
+            }
+            input[0] -= PREEMPHASIS * input[0];
+            input[..self.frame_length]
+                .iter_mut()
+                .zip(&self.window)
+                .for_each(|(x, w)| *x *= w);
+
+            fft.process(&mut input, &mut spectrum)
+                .expect("fft buffers have the planned size");
+            let power = spectrum.iter().map(|c| c.norm_sqr()).collect::<Vec<_>>();
+
+            features.push(
+                self.mel_banks
+                    .iter()
+                    .map(|bank| {
+                        bank.iter()
+                            .zip(&power)
+                            .map(|(w, p)| w * p)
+                            .sum::<f32>()
+                            .max(f32::EPSILON)
+                            .ln()
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        // cepstral mean normalization
+        let num_bins = self.mel_banks.len();
+        let means = (0..num_bins)
+            .map(|bin| features.iter().map(|frame| frame[bin]).sum::<f32>() / num_frames as f32)
+            .collect::<Vec<_>>();
+        features
+            .iter_mut()
+            .for_each(|frame| frame.iter_mut().zip(&means).for_each(|(x, mean)| *x -= mean));
+        features
+    }
+}
+
+fn mel(freq: f32) -> f32 {
+    1127.0 * (1.0 + freq / 700.0).ln()
+}
+
+fn mel_banks(sample_rate: usize, fft_size: usize, num_bins: usize) -> Vec<Vec<f32>> {
+    let mel_low = mel(LOW_FREQ);
+    let mel_high = mel(sample_rate as f32 / 2.0);
+    let mel_delta = (mel_high - mel_low) / (num_bins + 1) as f32;
+    let fft_bin_width = sample_rate as f32 / fft_size as f32;
+
+    (0..num_bins)
+        .map(|bin| {
+            let left = mel_low + bin as f32 * mel_delta;
+            let center = left + mel_delta;
+            let right = center + mel_delta;
+            (0..fft_size / 2 + 1)
+                .map(|fft_bin| {
+                    let mel = mel(fft_bin_width * fft_bin as f32);
+                    if mel <= left || mel >= right || fft_bin == fft_size / 2 {
+                        0.0
+                    } else if mel <= center {
+                        (mel - left) / (center - left)
+                    } else {
+                        (right - mel) / (right - center)
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fbank_shape() {
+        let samples = (0..16000)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin() * 10000.0)
+            .collect::<Vec<_>>();
+        let features = Fbank::new(16000, 80).compute(&samples);
+
+        assert_eq!(features.len(), 98);
+        assert!(features.iter().all(|frame| frame.len() == 80));
+        // mean normalized
+        let mean = features.iter().map(|frame| frame[10]).sum::<f32>() / features.len() as f32;
+        assert!(mean.abs() < 1e-3);
+    }
+}
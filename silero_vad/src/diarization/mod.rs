@@ -0,0 +1,259 @@
+//! Speaker diarization on top of the VAD segments: speaker embeddings are extracted from windows of every speech
+//! with a second ONNX model, clustered, and the speeches are split into turns labelled with speaker ids.
+
+mod clustering;
+mod embedding;
+mod fbank;
+
+pub use embedding::EmbeddingInput;
+
+use crate::recognizer::Recognizer;
+use crate::utils::TimeStamp;
+use embedding::SpeakerEmbedder;
+use lockfree_object_pool::MutexObjectPool;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct DiarizationParams {
+    pub embedding_input: EmbeddingInput,
+    /// Sample rate expected by the embedding model, the audio is resampled to it.
+    pub model_sample_rate: usize,
+    /// Speeches longer than `window_s` are split into windows of `window_s` every `step_s` seconds.
+    pub window_s: f32,
+    pub step_s: f32,
+    /// Speeches shorter than `min_segment_s` are not embedded and get the speaker of the closest speech.
+    pub min_segment_s: f32,
+    /// Minimal cosine similarity of the embeddings of one speaker.
+    pub similarity_threshold: f32,
+    /// Most windows clustered at once, the clustering taking quadratic memory in their number. Longer audio is
+    /// clustered in two stages, first in blocks of `max_clustered_windows` windows.
+    pub max_clustered_windows: usize,
+}
+
+impl Default for DiarizationParams {
+    fn default() -> Self {
+        Self {
+            embedding_input: EmbeddingInput::Fbank,
+            model_sample_rate: 16000,
+            window_s: 1.5,
+            step_s: 0.75,
+            min_segment_s: 0.3,
+            similarity_threshold: 0.5,
+            max_clustered_windows: 1000,
+        }
+    }
+}
+
+/// Constraints on the number of speakers, estimated from `similarity_threshold` when absent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpeakerHints {
+    pub min_speakers: Option<usize>,
+    pub max_speakers: Option<usize>,
+}
+
+impl SpeakerHints {
+    /// The number of speakers is known in advance, e.g. two-party phone calls.
+    pub fn exactly(num_speakers: usize) -> Self {
+        Self {
+            min_speakers: Some(num_speakers),
+            max_speakers: Some(num_speakers),
+        }
+    }
+}
+
+/// Part of a speech attributed to one speaker.
+#[derive(Debug, Clone)]
+pub struct SpeakerTurn {
+    pub timestamp: TimeStamp,
+    pub speaker: usize,
+}
+
+struct Window {
+    speech: usize,
+    start: usize,
+    end: usize,
+    speaker: Option<usize>,
+}
+
+pub struct Diarizer {
+    embedder_pool: MutexObjectPool<SpeakerEmbedder>,
+    params: DiarizationParams,
+}
+
+impl Diarizer {
+    pub fn new(model_path: &str, params: DiarizationParams, sessions_num: u8) -> crate::Result<Self> {
+        let onnx_sessions = (0..sessions_num)
+            .map(|_| Recognizer::make_onnx_session(model_path).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sessions_iter = parking_lot::Mutex::new(onnx_sessions.into_iter().cycle());
+
+        let input = params.embedding_input;
+        let model_sample_rate = params.model_sample_rate;
+        let embedder_pool = MutexObjectPool::<SpeakerEmbedder>::new(
+            move || {
+                let session = sessions_iter.lock().next().expect("no onnx sessions to cycle");
+                SpeakerEmbedder::new(session, input, model_sample_rate)
+            },
+            |_| {},
+        );
+
+        Ok(Self { embedder_pool, params })
+    }
+
+    /// Splits `speeches` detected in `samples` into speaker turns, the number of speakers being constrained by `hints`.
+    pub fn diarize(
+        &self,
+        samples: &[i16],
+        sample_rate: usize,
+        speeches: &[TimeStamp],
+        hints: SpeakerHints,
+    ) -> crate::Result<Vec<SpeakerTurn>> {
+        let mut windows = self.windows(samples.len(), sample_rate, speeches);
+
+        let mut embedder = self.embedder_pool.pull();
+        let mut embeddings = Vec::new();
+        let mut embedded_windows = Vec::new();
+        for (idx, window) in windows.iter().enumerate() {
+            let min_segment_samples = (self.params.min_segment_s * sample_rate as f32) as usize;
+            if window.end - window.start < min_segment_samples {
+                continue;
+            }
+            if let Some(embedding) = embedder.embed(&samples[window.start..window.end], sample_rate)? {
+                embeddings.push(embedding);
+                embedded_windows.push(idx);
+            }
+        }
+
+        let labels = clustering::cluster(
+            &embeddings,
+            self.params.similarity_threshold,
+            hints,
+            self.params.max_clustered_windows,
+        );
+        for (idx, label) in embedded_windows.into_iter().zip(labels) {
+            windows[idx].speaker = Some(label);
+        }
+        assign_closest_speaker(&mut windows);
+
+        Ok(turns(&windows, sample_rate))
+    }
+
+    fn windows(&self, samples_len: usize, sample_rate: usize, speeches: &[TimeStamp]) -> Vec<Window> {
+        let window_samples = ((self.params.window_s * sample_rate as f32) as usize).max(1);
+        let step_samples = ((self.params.step_s * sample_rate as f32) as usize).max(1);
+        let to_sample = |seconds: f64| ((seconds * sample_rate as f64).round() as usize).min(samples_len);
+
+        let mut windows = Vec::new();
+        for (speech, timestamp) in speeches.iter().enumerate() {
+            let (start, end) = (to_sample(timestamp.start), to_sample(timestamp.end));
+            let mut window_start = start;
+            loop {
+                // the last window is aligned to the end of the speech
+                let window_end = (window_start + window_samples).min(end);
+                let window_start_aligned = window_end.saturating_sub(window_samples).max(start).min(window_start);
+                windows.push(Window {
+                    speech,
+                    start: window_start_aligned,
+                    end: window_end,
+                    speaker: None,
+                });
+                if window_end >= end {
+                    break;
+                }
+                window_start += step_samples;
+            }
+        }
+        windows
+    }
+}
+
+/// Windows that were too short to be embedded get the speaker of the closest embedded window.
+fn assign_closest_speaker(windows: &mut [Window]) {
+    let labelled = windows
+        .iter()
+        .filter_map(|w| w.speaker.map(|speaker| (w.start, w.end, speaker)))
+        .collect::<Vec<_>>();
+    for window in windows.iter_mut().filter(|w| w.speaker.is_none()) {
+        window.speaker = labelled
+            .iter()
+            .min_by_key(|(start, end, _)| {
+                if *end <= window.start {
+                    window.start - end
+                } else {
+                    start.saturating_sub(window.end)
+                }
+            })
+            .map(|(_, _, speaker)| *speaker)
+            .or(Some(0));
+    }
+}
+
+/// Turns overlapping windows into consecutive turns, the boundary between two windows being the middle of their
+/// overlap, and merges the adjacent turns of the same speaker within a speech.
+fn turns(windows: &[Window], sample_rate: usize) -> Vec<SpeakerTurn> {
+    let to_seconds = |sample: usize| sample as f64 / sample_rate as f64;
+    let mut turns: Vec<(usize, SpeakerTurn)> = Vec::new();
+    for (idx, window) in windows.iter().enumerate() {
+        let speaker = window.speaker.unwrap_or_default();
+        let prev = idx
+            .checked_sub(1)
+            .map(|prev| &windows[prev])
+            .filter(|w| w.speech == window.speech);
+        let next = windows.get(idx + 1).filter(|w| w.speech == window.speech);
+        let start = prev.map_or(window.start, |prev| (window.start + prev.end.min(window.end)) / 2);
+        let end = next.map_or(window.end, |next| (next.start.max(window.start) + window.end) / 2);
+
+        match turns.last_mut() {
+            Some((speech, turn)) if *speech == window.speech && turn.speaker == speaker => {
+                turn.timestamp.end = to_seconds(end);
+            }
+            _ => turns.push((
+                window.speech,
+                SpeakerTurn {
+                    timestamp: TimeStamp {
+                        start: to_seconds(start),
+                        end: to_seconds(end),
+                        samples: None,
+                        stats: None,
+                    },
+                    speaker,
+                },
+            )),
+        }
+    }
+    turns.into_iter().map(|(_, turn)| turn).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(speech: usize, start: usize, end: usize, speaker: Option<usize>) -> Window {
+        Window {
+            speech,
+            start,
+            end,
+            speaker,
+        }
+    }
+
+    #[test]
+    fn test_turns() {
+        let mut windows = vec![
+            window(0, 0, 1500, Some(0)),
+            window(0, 750, 2250, Some(0)),
+            window(0, 1500, 3000, Some(1)),
+            window(1, 4000, 4200, None),
+            window(2, 6000, 7000, Some(1)),
+        ];
+        assign_closest_speaker(&mut windows);
+        let turns = turns(&windows, 1000);
+
+        let turns = turns
+            .iter()
+            .map(|t| (t.timestamp.start, t.timestamp.end, t.speaker))
+            .collect::<Vec<_>>();
+        assert_eq!(turns, vec![(0.0, 1.875, 0), (1.875, 3.0, 1), (4.0, 4.2, 1), (6.0, 7.0, 1)]);
+    }
+}
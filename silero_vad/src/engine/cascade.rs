@@ -0,0 +1,133 @@
+use crate::engine::{EnergyVad, VadEngine};
+use crate::snapshot::{Reader, Writer};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Energy probability below which a frame is taken for silence without running the model, about 2 dB above the
+/// noise floor.
+const GATE_PROBABILITY: f32 = 0.1;
+/// The model keeps running while its last probability is at least this, so that the ends of speeches are decided by
+/// the model rather than by the gate.
+const HANGOVER_PROBABILITY: f32 = 0.1;
+
+/// Counters of the frames seen by cascades, shared by all the cascades of a recognizer.
+#[derive(Debug, Default)]
+pub struct CascadeStats {
+    frames: AtomicU64,
+    skipped_frames: AtomicU64,
+}
+
+impl CascadeStats {
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    /// Frames the model didn't run on.
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Share of the frames the model didn't run on, `0` before any frame.
+    pub fn skipped_fraction(&self) -> f64 {
+        match self.frames() {
+            0 => 0.0,
+            frames => self.skipped_frames() as f64 / frames as f64,
+        }
+    }
+}
+
+/// Two-stage VAD: an energy gate finds the frames that can't be speech, which get a zero probability, and the model
+/// only runs on the other frames.
+pub struct CascadeVad {
+    gate: EnergyVad,
+    model: Box<dyn VadEngine>,
+    stats: Arc<CascadeStats>,
+    last_probability: f32,
+}
+
+impl CascadeVad {
+    pub fn new(sample_rate: usize, model: Box<dyn VadEngine>, stats: Arc<CascadeStats>) -> Self {
+        Self {
+            gate: EnergyVad::gate(sample_rate),
+            model,
+            stats,
+            last_probability: 0.0,
+        }
+    }
+}
+
+impl VadEngine for CascadeVad {
+    fn process_frame(&mut self, frame: &[i16]) -> crate::Result<f32> {
+        // the gate runs on every frame to keep tracking the noise floor
+        let gate_probability = self.gate.process_frame(frame)?;
+        self.stats.frames.fetch_add(1, Ordering::Relaxed);
+        if gate_probability < GATE_PROBABILITY && self.last_probability < HANGOVER_PROBABILITY {
+            self.stats.skipped_frames.fetch_add(1, Ordering::Relaxed);
+            self.last_probability = 0.0;
+            return Ok(0.0);
+        }
+        self.last_probability = self.model.process_frame(frame)?;
+        Ok(self.last_probability)
+    }
+
+    fn reset(&mut self) {
+        self.gate.reset();
+        self.model.reset();
+        self.last_probability = 0.0;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes(&self.gate.save_state());
+        writer.bytes(&self.model.save_state());
+        writer.f32(self.last_probability);
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> crate::Result<()> {
+        let mut reader = Reader::new(state);
+        self.gate.load_state(reader.bytes()?)?;
+        self.model.load_state(reader.bytes()?)?;
+        self.last_probability = reader.f32()?;
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::{noise, tone};
+
+    /// Model calling every frame speech, counting the frames it runs on.
+    struct CountingModel(Arc<AtomicU64>);
+
+    impl VadEngine for CountingModel {
+        fn process_frame(&mut self, _frame: &[i16]) -> crate::Result<f32> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(1.0)
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    #[test]
+    fn test_cascade_skips_silence() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let stats = Arc::new(CascadeStats::default());
+        let mut vad = CascadeVad::new(16000, Box::new(CountingModel(calls.clone())), stats.clone());
+
+        let mut probabilities = Vec::new();
+        for samples in [noise(16000, 300), tone(16000, 8000)] {
+            for frame in samples.chunks_exact(512) {
+                probabilities.push(vad.process_frame(frame).unwrap());
+            }
+        }
+        let (silence, speech) = probabilities.split_at(31);
+        assert!(silence.iter().all(|&p| p == 0.0), "{:?}", silence);
+        assert!(speech.iter().all(|&p| p == 1.0), "{:?}", speech);
+        assert_eq!(calls.load(Ordering::Relaxed), 31);
+        assert_eq!(stats.frames(), 62);
+        assert_eq!(stats.skipped_frames(), 31);
+        assert_eq!(stats.skipped_fraction(), 0.5);
+    }
+}
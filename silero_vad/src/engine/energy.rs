@@ -0,0 +1,128 @@
+use crate::engine::{energy_db, sigmoid, VadEngine, I16_SCALE};
+use crate::snapshot::{Reader, Writer};
+
+/// Energy above the noise floor at which the speech probability is 0.5.
+const MARGIN_DB: f32 = 9.0;
+/// Energy change turning the probability from 0.5 to about 0.73.
+const SLOPE_DB: f32 = 3.0;
+/// Frames quieter than this are never speech.
+const MIN_SPEECH_DB: f32 = -55.0;
+/// Rise of the noise floor per second of non-speech, and of speech so that a lasting louder noise is learnt too.
+const FLOOR_RISE_DB_PER_S: f32 = 6.0;
+const FLOOR_RISE_IN_SPEECH_DB_PER_S: f32 = 1.0;
+/// Share of the distance to a quieter frame the noise floor falls by.
+const FLOOR_FALL: f32 = 0.5;
+/// Zero-crossing rate above which a frame sounds like broadband noise, voiced speech stays well below it. The
+/// probability falls to 0 over the next `ZCR_FALLOFF`, white noise crossing zero at every other sample.
+const NOISE_ZCR: f32 = 0.35;
+const ZCR_FALLOFF: f32 = 0.1;
+
+/// Cheap VAD comparing the frame energy to a tracked noise floor, discounting frames with the zero-crossing rate of
+/// broadband noise.
+pub struct EnergyVad {
+    sample_rate: usize,
+    noise_floor_db: Option<f32>,
+    zcr_discount: bool,
+}
+
+impl EnergyVad {
+    pub fn new(sample_rate: usize) -> Self {
+        Self {
+            sample_rate,
+            noise_floor_db: None,
+            zcr_discount: true,
+        }
+    }
+
+    /// Energy VAD without the zero-crossing discount, so that unvoiced speech isn't missed when it only gates
+    /// another engine.
+    pub(crate) fn gate(sample_rate: usize) -> Self {
+        Self {
+            zcr_discount: false,
+            ..Self::new(sample_rate)
+        }
+    }
+}
+
+impl VadEngine for EnergyVad {
+    fn process_frame(&mut self, frame: &[i16]) -> crate::Result<f32> {
+        if frame.is_empty() {
+            return Ok(0.0);
+        }
+        let power = frame.iter().map(|&x| (x as f32 / I16_SCALE).powi(2)).sum::<f32>() / frame.len() as f32;
+        let energy = energy_db(power);
+        let crossings = frame.windows(2).filter(|pair| (pair[0] >= 0) != (pair[1] >= 0)).count();
+        let zcr = crossings as f32 / (frame.len() - 1).max(1) as f32;
+
+        // the audio usually starts with non-speech
+        let floor = *self.noise_floor_db.get_or_insert(energy);
+        let mut probability = if energy < MIN_SPEECH_DB {
+            0.0
+        } else {
+            sigmoid((energy - floor - MARGIN_DB) / SLOPE_DB)
+        };
+        if self.zcr_discount && zcr > NOISE_ZCR {
+            probability *= (1.0 - (zcr - NOISE_ZCR) / ZCR_FALLOFF).max(0.0);
+        }
+
+        let duration_s = frame.len() as f32 / self.sample_rate as f32;
+        let rise = if probability > 0.5 {
+            FLOOR_RISE_IN_SPEECH_DB_PER_S
+        } else {
+            FLOOR_RISE_DB_PER_S
+        };
+        self.noise_floor_db = Some(if energy < floor {
+            floor + FLOOR_FALL * (energy - floor)
+        } else {
+            floor + (energy - floor).min(rise * duration_s)
+        });
+        Ok(probability)
+    }
+
+    fn reset(&mut self) {
+        self.noise_floor_db = None;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bool(self.noise_floor_db.is_some());
+        writer.f32(self.noise_floor_db.unwrap_or_default());
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> crate::Result<()> {
+        let mut reader = Reader::new(state);
+        let has_floor = reader.bool()?;
+        let floor = reader.f32()?;
+        reader.finish()?;
+        self.noise_floor_db = has_floor.then_some(floor);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::{noise, tone};
+
+    #[test]
+    fn test_energy_vad() {
+        let mut vad = EnergyVad::new(16000);
+        let mut probabilities = Vec::new();
+        for samples in [noise(16000, 300), tone(16000, 8000), noise(16000, 300)] {
+            for frame in samples.chunks_exact(512) {
+                probabilities.push(vad.process_frame(frame).unwrap());
+            }
+        }
+        let (first_noise, rest) = probabilities.split_at(31);
+        let (speech, last_noise) = rest.split_at(31);
+        assert!(first_noise.iter().all(|&p| p < 0.1), "{:?}", first_noise);
+        assert!(speech.iter().all(|&p| p > 0.9), "{:?}", speech);
+        assert!(last_noise.iter().all(|&p| p < 0.1), "{:?}", last_noise);
+
+        // broadband noise as loud as the speech
+        vad.reset();
+        vad.process_frame(&noise(512, 30)).unwrap();
+        assert!(vad.process_frame(&noise(512, 8000)).unwrap() < 0.5);
+    }
+}
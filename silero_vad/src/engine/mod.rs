@@ -0,0 +1,112 @@
+//! Frame-level voice activity detectors driven by `VadIter`: Silero, a WebRTC-style GMM VAD and an energy and
+//! zero-crossing VAD, and a cascade gating Silero with the energy VAD. The speech segmentation on top of the frame
+//! probabilities is shared by all engines.
+
+mod cascade;
+mod energy;
+mod webrtc;
+
+pub use cascade::{CascadeStats, CascadeVad};
+pub use energy::EnergyVad;
+pub use webrtc::{WebRtcMode, WebRtcVad};
+
+/// Computes the speech probability of consecutive audio frames, keeping the state carried from frame to frame.
+pub trait VadEngine: Send {
+    /// Returns the speech probability in `[0, 1]` of the next frame of the audio.
+    fn process_frame(&mut self, frame: &[i16]) -> crate::Result<f32>;
+
+    /// Forgets the state of the previous audio.
+    fn reset(&mut self);
+
+    /// Encodes the state carried from frame to frame, to resume the audio with another engine of the same kind.
+    /// Stateless engines have an empty state.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores the state encoded by `save_state`.
+    fn load_state(&mut self, state: &[u8]) -> crate::Result<()> {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err(crate::snapshot::invalid("the engine has no state"))
+        }
+    }
+}
+
+/// The available engines, the models of Silero are loaded from a file while the other engines need no model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EngineKind {
+    #[default]
+    Silero,
+    WebRtc,
+    Energy,
+}
+
+/// Full scale of 16-bit samples.
+pub(crate) const I16_SCALE: f32 = 32768.0;
+
+/// Energy in dB relative to the full scale, `-100` for digital silence.
+pub(crate) fn energy_db(power: f32) -> f32 {
+    10.0 * power.max(1e-10).log10()
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::VadEngine;
+
+    /// Engine returning a scripted sequence of probabilities, then silence. `reset` replays the script.
+    pub struct ScriptedEngine {
+        probabilities: Vec<f32>,
+        position: usize,
+    }
+
+    impl ScriptedEngine {
+        pub fn new(probabilities: &[f32]) -> Self {
+            Self {
+                probabilities: probabilities.to_vec(),
+                position: 0,
+            }
+        }
+    }
+
+    impl VadEngine for ScriptedEngine {
+        fn process_frame(&mut self, _frame: &[i16]) -> crate::Result<f32> {
+            let probability = self.probabilities.get(self.position).copied().unwrap_or(0.0);
+            self.position += 1;
+            Ok(probability)
+        }
+
+        fn reset(&mut self) {
+            self.position = 0;
+        }
+    }
+
+    /// Deterministic white noise of the given peak amplitude.
+    pub fn noise(len: usize, amplitude: i16) -> Vec<i16> {
+        let mut seed = 12345u32;
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (((seed >> 16) as f32 / 32768.0 - 1.0) * amplitude as f32) as i16
+            })
+            .collect()
+    }
+
+    /// Voiced speech-like signal at 16 kHz: harmonics of 150 Hz up to 3 kHz with a falling spectrum.
+    pub fn tone(len: usize, amplitude: i16) -> Vec<i16> {
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / 16000.0;
+                let x = (1..=20)
+                    .map(|h| (2.0 * std::f32::consts::PI * 150.0 * h as f32 * t).sin() / h as f32)
+                    .sum::<f32>();
+                (x * amplitude as f32 / 2.0) as i16
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,321 @@
+use crate::engine::{energy_db, sigmoid, VadEngine, I16_SCALE};
+use crate::snapshot::{invalid, Reader, Writer};
+use realfft::{RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+/// Sub-bands of the narrowband spectrum the features are computed in, as in the WebRTC VAD.
+const BANDS_HZ: [(f32, f32); 6] = [
+    (80.0, 250.0),
+    (250.0, 500.0),
+    (500.0, 1000.0),
+    (1000.0, 2000.0),
+    (2000.0, 3000.0),
+    (3000.0, 4000.0),
+];
+/// The frames are analysed in 10 ms sub-frames.
+const SUBFRAME_MS: usize = 10;
+
+/// Initial mixtures of the log band energies in dB, two Gaussians of equal weight for noise and for speech.
+const NOISE_MEANS_DB: [f32; 2] = [-80.0, -65.0];
+const NOISE_STD_DB: f32 = 6.0;
+const SPEECH_MEANS_DB: [f32; 2] = [-50.0, -35.0];
+const SPEECH_STD_DB: f32 = 8.0;
+const MIN_STD_DB: f32 = 1.5;
+const MAX_STD_DB: f32 = 20.0;
+/// The lower Gaussian of the speech model is kept this far above the mean of the noise model so that they can't swap.
+const MIN_SEPARATION_DB: f32 = 10.0;
+/// Adaptation rates of the model of the decided class.
+const NOISE_RATE: f32 = 0.02;
+const SPEECH_RATE: f32 = 0.005;
+/// The noise model is learnt from the first sub-frames whatever the decision, the audio usually starting with
+/// non-speech.
+const INIT_SUBFRAMES: usize = 20;
+
+/// Aggressiveness of the WebRTC VAD, the more aggressive modes report less noise as speech and miss more speech.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WebRtcMode {
+    Quality,
+    LowBitrate,
+    #[default]
+    Aggressive,
+    VeryAggressive,
+}
+
+impl WebRtcMode {
+    /// Log-likelihood ratio summed over the bands at which the speech probability is 0.5.
+    fn bias(self) -> f32 {
+        match self {
+            WebRtcMode::Quality => 1.5,
+            WebRtcMode::LowBitrate => 3.0,
+            WebRtcMode::Aggressive => 6.0,
+            WebRtcMode::VeryAggressive => 9.0,
+        }
+    }
+}
+
+impl TryFrom<u8> for WebRtcMode {
+    type Error = crate::error::VadError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(WebRtcMode::Quality),
+            1 => Ok(WebRtcMode::LowBitrate),
+            2 => Ok(WebRtcMode::Aggressive),
+            3 => Ok(WebRtcMode::VeryAggressive),
+            _ => Err(crate::error::VadError::VadError(format!("Invalid WebRTC VAD mode: {}", value))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Gaussian {
+    mean: f32,
+    std: f32,
+}
+
+impl Gaussian {
+    fn log_pdf(&self, x: f32) -> f32 {
+        let z = (x - self.mean) / self.std;
+        -0.5 * z * z - self.std.ln() - 0.5 * (2.0 * std::f32::consts::PI).ln()
+    }
+}
+
+/// Log-likelihood of `x` under the equal-weight mixture, with the responsibility of every Gaussian.
+fn mixture_log_likelihood(mixture: &[Gaussian; 2], x: f32) -> (f32, [f32; 2]) {
+    let log_pdfs = mixture.map(|g| g.log_pdf(x) + 0.5f32.ln());
+    let max = log_pdfs[0].max(log_pdfs[1]);
+    let sum = log_pdfs.iter().map(|l| (l - max).exp()).sum::<f32>();
+    (max + sum.ln(), log_pdfs.map(|l| (l - max).exp() / sum))
+}
+
+fn adapt(mixture: &mut [Gaussian; 2], x: f32, responsibilities: [f32; 2], rate: f32) {
+    for (g, r) in mixture.iter_mut().zip(responsibilities) {
+        let delta = x - g.mean;
+        g.mean += rate * r * delta;
+        let variance = g.std * g.std + rate * r * (delta * delta - g.std * g.std);
+        g.std = variance.sqrt().clamp(MIN_STD_DB, MAX_STD_DB);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BandModel {
+    noise: [Gaussian; 2],
+    speech: [Gaussian; 2],
+}
+
+impl Default for BandModel {
+    fn default() -> Self {
+        Self {
+            noise: NOISE_MEANS_DB.map(|mean| Gaussian {
+                mean,
+                std: NOISE_STD_DB,
+            }),
+            speech: SPEECH_MEANS_DB.map(|mean| Gaussian {
+                mean,
+                std: SPEECH_STD_DB,
+            }),
+        }
+    }
+}
+
+/// VAD in the style of the WebRTC one: the log energies of six sub-bands are scored by Gaussian mixture models of
+/// noise and speech, and the model of the decided class adapts to the audio.
+pub struct WebRtcVad {
+    mode: WebRtcMode,
+    subframe_len: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    window_power: f32,
+    bands: Vec<std::ops::Range<usize>>,
+    models: Vec<BandModel>,
+    /// Samples of an incomplete sub-frame carried to the next frame.
+    pending: Vec<i16>,
+    subframes_seen: usize,
+    last_probability: f32,
+}
+
+impl WebRtcVad {
+    pub fn new(sample_rate: usize, mode: WebRtcMode) -> Self {
+        let subframe_len = sample_rate * SUBFRAME_MS / 1000;
+        let bin_hz = sample_rate as f32 / subframe_len as f32;
+        let bands = BANDS_HZ
+            .iter()
+            .map(|&(low, high)| {
+                let last_bin = subframe_len / 2;
+                (low / bin_hz).ceil() as usize..((high / bin_hz).ceil() as usize).min(last_bin)
+            })
+            .collect::<Vec<_>>();
+        // hann window
+        let window = (0..subframe_len)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / subframe_len as f32).cos())
+            .collect::<Vec<f32>>();
+        Self {
+            mode,
+            subframe_len,
+            fft: RealFftPlanner::new().plan_fft_forward(subframe_len),
+            window_power: window.iter().map(|w| w * w).sum(),
+            window,
+            models: vec![BandModel::default(); bands.len()],
+            bands,
+            pending: Vec::new(),
+            subframes_seen: 0,
+            last_probability: 0.0,
+        }
+    }
+
+    /// Log energies in dB of the sub-bands of a sub-frame.
+    fn band_energies(&self, subframe: &[i16]) -> Vec<f32> {
+        let mut input = subframe
+            .iter()
+            .zip(&self.window)
+            .map(|(&x, w)| x as f32 / I16_SCALE * w)
+            .collect::<Vec<_>>();
+        let mut spectrum = self.fft.make_output_vec();
+        self.fft
+            .process(&mut input, &mut spectrum)
+            .expect("fft buffers have the planned size");
+        // one-sided power spectrum normalized to the mean power of the signal
+        let scale = 2.0 / (self.subframe_len as f32 * self.window_power);
+        self.bands
+            .iter()
+            .map(|band| energy_db(spectrum[band.clone()].iter().map(|c| c.norm_sqr()).sum::<f32>() * scale))
+            .collect()
+    }
+
+    /// Scores a sub-frame, returning the log-likelihood ratio of speech to noise summed over the bands.
+    fn score_subframe(&mut self, subframe: &[i16]) -> f32 {
+        let energies = self.band_energies(subframe);
+        let scores = self
+            .models
+            .iter()
+            .zip(&energies)
+            .map(|(model, &x)| (mixture_log_likelihood(&model.speech, x), mixture_log_likelihood(&model.noise, x)))
+            .collect::<Vec<_>>();
+        let llr = scores
+            .iter()
+            .map(|((speech, _), (noise, _))| speech - noise)
+            .sum::<f32>();
+
+        self.subframes_seen += 1;
+        let initializing = self.subframes_seen <= INIT_SUBFRAMES;
+        let is_speech = llr > self.mode.bias();
+        for ((model, &x), ((_, speech), (_, noise))) in self.models.iter_mut().zip(&energies).zip(scores) {
+            if initializing {
+                // running average over the first sub-frames
+                adapt(&mut model.noise, x, noise, 1.0 / self.subframes_seen as f32);
+            } else if is_speech {
+                adapt(&mut model.speech, x, speech, SPEECH_RATE);
+            } else {
+                adapt(&mut model.noise, x, noise, NOISE_RATE);
+            }
+            let noise_mean = model.noise.iter().map(|g| g.mean).sum::<f32>() / 2.0;
+            let speech_min = model.speech.iter().map(|g| g.mean).fold(f32::INFINITY, f32::min);
+            let shift = noise_mean + MIN_SEPARATION_DB - speech_min;
+            if shift > 0.0 {
+                model.speech.iter_mut().for_each(|g| g.mean += shift);
+            }
+        }
+        llr
+    }
+}
+
+impl VadEngine for WebRtcVad {
+    fn process_frame(&mut self, frame: &[i16]) -> crate::Result<f32> {
+        let mut samples = std::mem::take(&mut self.pending);
+        samples.extend_from_slice(frame);
+        let subframes = samples.len() / self.subframe_len;
+        if subframes == 0 {
+            self.pending = samples;
+            return Ok(self.last_probability);
+        }
+
+        let llr = samples
+            .chunks_exact(self.subframe_len)
+            .map(|subframe| self.score_subframe(subframe))
+            .sum::<f32>()
+            / subframes as f32;
+        self.pending = samples[subframes * self.subframe_len..].to_vec();
+        self.last_probability = sigmoid(llr - self.mode.bias());
+        Ok(self.last_probability)
+    }
+
+    fn reset(&mut self) {
+        self.models.iter_mut().for_each(|model| *model = BandModel::default());
+        self.pending.clear();
+        self.subframes_seen = 0;
+        self.last_probability = 0.0;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        let gaussians = self
+            .models
+            .iter()
+            .flat_map(|model| model.noise.iter().chain(&model.speech))
+            .flat_map(|g| [g.mean, g.std])
+            .collect::<Vec<_>>();
+        writer.f32s(&gaussians);
+        writer.i16s(&self.pending);
+        writer.usize(self.subframes_seen);
+        writer.f32(self.last_probability);
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> crate::Result<()> {
+        let mut reader = Reader::new(state);
+        let gaussians = reader.f32s()?;
+        let pending = reader.i16s()?;
+        let subframes_seen = reader.usize()?;
+        let last_probability = reader.f32()?;
+        reader.finish()?;
+        // mean and std of the two noise then the two speech gaussians of every band
+        if gaussians.len() != self.models.len() * 8 || pending.len() >= self.subframe_len {
+            return Err(invalid("WebRTC VAD state of another sample rate"));
+        }
+        for (model, values) in self.models.iter_mut().zip(gaussians.chunks_exact(8)) {
+            let mut values = values.chunks_exact(2).map(|pair| Gaussian {
+                mean: pair[0],
+                std: pair[1],
+            });
+            model.noise = [(); 2].map(|_| values.next().expect("8 values per band"));
+            model.speech = [(); 2].map(|_| values.next().expect("8 values per band"));
+        }
+        self.pending = pending;
+        self.subframes_seen = subframes_seen;
+        self.last_probability = last_probability;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::{noise, tone};
+
+    #[test]
+    fn test_webrtc_vad() {
+        for mode in [WebRtcMode::Quality, WebRtcMode::VeryAggressive] {
+            let mut vad = WebRtcVad::new(16000, mode);
+            let mut probabilities = Vec::new();
+            for samples in [noise(16000, 300), tone(16000, 8000), noise(16000, 300)] {
+                for frame in samples.chunks_exact(512) {
+                    probabilities.push(vad.process_frame(frame).unwrap());
+                }
+            }
+            // the first frame of a segment may still hold a sub-frame of the previous one
+            let (first_noise, rest) = probabilities.split_at(31);
+            let (speech, last_noise) = rest.split_at(31);
+            assert!(first_noise.iter().all(|&p| p < 0.1), "{:?} {:?}", mode, first_noise);
+            assert!(speech[1..].iter().all(|&p| p > 0.9), "{:?} {:?}", mode, speech);
+            assert!(last_noise[1..].iter().all(|&p| p < 0.1), "{:?} {:?}", mode, last_noise);
+        }
+    }
+
+    #[test]
+    fn test_webrtc_bands_at_8k() {
+        let vad = WebRtcVad::new(8000, WebRtcMode::default());
+        assert_eq!(vad.subframe_len, 80);
+        assert_eq!(vad.bands.first(), Some(&(1..3)));
+        assert_eq!(vad.bands.last(), Some(&(30..40)));
+    }
+}
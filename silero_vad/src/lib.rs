@@ -1,18 +1,28 @@
 mod silero;
+mod smoothing;
+mod snapshot;
+mod threshold;
 pub mod utils;
 mod vad_iter;
 
+pub mod compact;
+pub mod diarization;
+pub mod engine;
 pub mod error;
 pub mod recognizer;
+pub mod stream;
+pub mod summary;
 pub mod tools;
 
+pub use engine::{EngineKind, VadEngine};
 pub use recognizer::Recognizer;
+pub use stream::{VadEvent, VadStream};
+pub use summary::SpeechSummary;
+pub use utils::Smoothing;
+pub use utils::SpeechStats;
 pub use utils::TimeStamp;
 pub use utils::VadParams;
 
 pub type OnnxSession = ort::session::Session;
 
 pub type Result<T> = std::result::Result<T, error::VadError>;
-
-#[cfg(test)]
-mod tests {}
@@ -1,43 +1,81 @@
+use crate::engine::{CascadeStats, CascadeVad, VadEngine};
 use crate::utils::{TimeStamp, VadParams};
-use crate::{error, silero, vad_iter, OnnxSession};
+use crate::{error, silero, stream, vad_iter, OnnxSession};
 use lockfree_object_pool::MutexObjectPool;
 use ort::session::builder::GraphOptimizationLevel;
-use std::iter::Cycle;
 use std::sync::Arc;
-use std::vec::IntoIter;
 
 pub struct Recognizer {
     vad_iter_pool: Arc<MutexObjectPool<vad_iter::VadIter>>,
-    sessions: Arc<parking_lot::Mutex<Cycle<IntoIter<Arc<OnnxSession>>>>>,
+    sample_rate: usize,
+    cascade_stats: Option<Arc<CascadeStats>>,
 }
 
 impl Recognizer {
     pub fn new(model_path: &str, vad_params: VadParams, sessions_num: u8) -> Result<Self, error::VadError> {
-        let onnx_sessions = (0..sessions_num)
-            .map(|_| Recognizer::make_onnx_session(model_path).map(Arc::new))
-            .collect::<Result<Vec<_>, _>>()?;
+        let make_silero = Self::make_silero(model_path, vad_params.sample_rate, sessions_num)?;
+
+        Ok(Self::with_engine(vad_params, move || Box::new(make_silero())))
+    }
 
-        let sessions_iter = Arc::new(parking_lot::Mutex::new(onnx_sessions.into_iter().cycle()));
+    /// Creates a recognizer running Silero only on the frames an energy gate doesn't take for silence, the skipped
+    /// frames are counted in `cascade_stats`.
+    pub fn cascade(model_path: &str, vad_params: VadParams, sessions_num: u8) -> Result<Self, error::VadError> {
+        let make_silero = Self::make_silero(model_path, vad_params.sample_rate, sessions_num)?;
+        let sample_rate = vad_params.sample_rate;
+        let stats = Arc::new(CascadeStats::default());
 
-        let sample_rate = vad_params.sample_rate.into();
+        let engine_stats = stats.clone();
+        let mut recognizer = Self::with_engine(vad_params, move || {
+            Box::new(CascadeVad::new(sample_rate, Box::new(make_silero()), engine_stats.clone()))
+        });
+        recognizer.cascade_stats = Some(stats);
+        Ok(recognizer)
+    }
+
+    /// Creates a recognizer running the engines made by `make_engine`, one per concurrent recognition.
+    pub fn with_engine(
+        vad_params: VadParams,
+        make_engine: impl Fn() -> Box<dyn VadEngine> + Send + Sync + 'static,
+    ) -> Self {
+        let sample_rate = vad_params.sample_rate;
 
-        let sessions_iter_clone = sessions_iter.clone();
         let vad_iter_pool = MutexObjectPool::<vad_iter::VadIter>::new(
-            move || {
-                let session = sessions_iter_clone.lock().next().expect("no onnx sessions to cycle");
-                let silero = silero::SileroSession::new(session, sample_rate).expect("error creating Silero session");
-                vad_iter::VadIter::new(silero, vad_params.clone())
-            },
+            move || vad_iter::VadIter::new(make_engine(), vad_params.clone()),
             |_| {},
         );
 
-        Ok(Self {
+        Self {
             vad_iter_pool: Arc::new(vad_iter_pool),
-            sessions: sessions_iter,
+            sample_rate,
+            cascade_stats: None,
+        }
+    }
+
+    /// Frames skipped by the energy gate of a cascade recognizer, `None` for the other recognizers.
+    pub fn cascade_stats(&self) -> Option<&CascadeStats> {
+        self.cascade_stats.as_deref()
+    }
+
+    /// Makes Silero sessions sharing `sessions_num` ONNX sessions in turn.
+    fn make_silero(
+        model_path: &str,
+        sample_rate: usize,
+        sessions_num: u8,
+    ) -> crate::Result<impl Fn() -> silero::SileroSession + Send + Sync + 'static> {
+        let onnx_sessions = (0..sessions_num)
+            .map(|_| Recognizer::make_onnx_session(model_path).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sessions_iter = parking_lot::Mutex::new(onnx_sessions.into_iter().cycle());
+
+        Ok(move || {
+            let session = sessions_iter.lock().next().expect("no onnx sessions to cycle");
+            silero::SileroSession::new(session, sample_rate.into()).expect("error creating Silero session")
         })
     }
 
-    fn make_onnx_session(model_path: &str) -> crate::Result<OnnxSession> {
+    pub(crate) fn make_onnx_session(model_path: &str) -> crate::Result<OnnxSession> {
         let session = OnnxSession::builder()?
             .with_inter_threads(1)?
             // .with_execution_providers(vec![CUDAExecutionProvider::default().build()]).expect("onnx session providers error")
@@ -52,4 +90,15 @@ impl Recognizer {
         vad.process(samples)?;
         Ok(vad.speeches())
     }
+
+    /// Starts a streaming session. The session holds one of the pooled VAD iterators until it is dropped.
+    pub fn stream(&self) -> stream::VadStream {
+        stream::VadStream::new(self.vad_iter_pool.pull_owned(), self.sample_rate)
+    }
+
+    /// Resumes a streaming session from the state saved by `VadStream::save_state` with a recognizer of the same
+    /// engine and parameters.
+    pub fn resume_stream(&self, state: &[u8]) -> Result<stream::VadStream, error::VadError> {
+        stream::VadStream::resume(self.vad_iter_pool.pull_owned(), self.sample_rate, state)
+    }
 }
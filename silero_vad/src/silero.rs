@@ -1,3 +1,5 @@
+use crate::engine::VadEngine;
+use crate::snapshot::{invalid, Reader, Writer};
 use crate::{utils, OnnxSession};
 use ndarray::{s, Array, Array2, ArrayBase, ArrayD, Dim, IxDynImpl, OwnedRepr};
 use std::sync::Arc;
@@ -42,3 +44,28 @@ impl SileroSession {
         Ok(*res["output"].try_extract_raw_tensor::<f32>()?.1.first().unwrap())
     }
 }
+
+impl VadEngine for SileroSession {
+    fn process_frame(&mut self, frame: &[i16]) -> crate::Result<f32> {
+        Ok(self.calc_level(frame)?)
+    }
+
+    fn reset(&mut self) {
+        SileroSession::reset(self)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.f32s(&self.state.iter().copied().collect::<Vec<_>>());
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> crate::Result<()> {
+        let mut reader = Reader::new(state);
+        let values = reader.f32s()?;
+        reader.finish()?;
+        self.state =
+            ArrayD::from_shape_vec([2, 1, 128].as_slice(), values).map_err(|_| invalid("Silero state shape"))?;
+        Ok(())
+    }
+}
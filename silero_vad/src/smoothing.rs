@@ -0,0 +1,74 @@
+use crate::utils::Smoothing;
+use std::collections::VecDeque;
+
+/// Smooths the speech probabilities over the last frames, delaying the changes by about half the window.
+#[derive(Debug)]
+pub(crate) struct Smoother {
+    smoothing: Smoothing,
+    window: VecDeque<f32>,
+}
+
+impl Smoother {
+    pub fn new(smoothing: Smoothing) -> Self {
+        Self {
+            smoothing,
+            window: VecDeque::with_capacity(smoothing.frames()),
+        }
+    }
+
+    /// Adds the probability of the next frame and returns the smoothed probability of the window.
+    pub fn update(&mut self, speech_prob: f32) -> f32 {
+        if self.window.len() == self.smoothing.frames().max(1) {
+            self.window.pop_front();
+        }
+        self.window.push_back(speech_prob);
+        match self.smoothing {
+            Smoothing::MovingAverage(_) => self.window.iter().sum::<f32>() / self.window.len() as f32,
+            Smoothing::Median(_) => {
+                let mut sorted = self.window.iter().copied().collect::<Vec<_>>();
+                sorted.sort_by(f32::total_cmp);
+                let mid = sorted.len() / 2;
+                if sorted.len() % 2 == 0 {
+                    (sorted[mid - 1] + sorted[mid]) / 2.0
+                } else {
+                    sorted[mid]
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.window.clear();
+    }
+
+    pub fn window(&self) -> Vec<f32> {
+        self.window.iter().copied().collect()
+    }
+
+    pub fn load_window(&mut self, window: Vec<f32>) -> crate::Result<()> {
+        if window.len() > self.smoothing.frames().max(1) {
+            return Err(crate::snapshot::invalid("window longer than configured"));
+        }
+        self.window = window.into();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smoothing() {
+        let probabilities = [0.0, 0.0, 0.9, 0.0, 0.0, 0.9, 0.9, 0.9];
+
+        let mut median = Smoother::new(Smoothing::Median(3));
+        let smoothed = probabilities.map(|p| median.update(p));
+        // the isolated spike is removed, the lasting speech gets through one frame late
+        assert_eq!(smoothed, [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.9, 0.9]);
+
+        let mut average = Smoother::new(Smoothing::MovingAverage(2));
+        let smoothed = probabilities.map(|p| average.update(p));
+        assert_eq!(smoothed, [0.0, 0.0, 0.45, 0.45, 0.0, 0.45, 0.9, 0.9]);
+    }
+}
@@ -0,0 +1,168 @@
+//! Binary encoding of the state of a VAD session, to resume a stream elsewhere. Numbers are little-endian and
+//! sequences are prefixed by their length.
+
+use crate::error::VadError;
+
+#[derive(Debug, Default)]
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    pub fn f32s(&mut self, values: &[f32]) {
+        self.usize(values.len());
+        values.iter().for_each(|&value| self.f32(value));
+    }
+
+    pub fn i16s(&mut self, values: &[i16]) {
+        self.usize(values.len());
+        values
+            .iter()
+            .for_each(|value| self.bytes.extend_from_slice(&value.to_le_bytes()));
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        let (head, tail) = self
+            .bytes
+            .split_first_chunk::<N>()
+            .ok_or_else(|| invalid("truncated"))?;
+        self.bytes = tail;
+        Ok(*head)
+    }
+
+    /// Reads a length, checking that the rest of the state holds at least that many items of `item_size` bytes.
+    fn len(&mut self, item_size: usize) -> crate::Result<usize> {
+        let len = self.usize()?;
+        if len.saturating_mul(item_size) > self.bytes.len() {
+            return Err(invalid("truncated"));
+        }
+        Ok(len)
+    }
+
+    pub fn u64(&mut self) -> crate::Result<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub fn usize(&mut self) -> crate::Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| invalid("size out of range"))
+    }
+
+    pub fn i64(&mut self) -> crate::Result<i64> {
+        self.take().map(i64::from_le_bytes)
+    }
+
+    pub fn f32(&mut self) -> crate::Result<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    pub fn bool(&mut self) -> crate::Result<bool> {
+        match self.take::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(invalid("invalid boolean")),
+        }
+    }
+
+    pub fn f32s(&mut self) -> crate::Result<Vec<f32>> {
+        let len = self.len(4)?;
+        (0..len).map(|_| self.f32()).collect()
+    }
+
+    pub fn i16s(&mut self) -> crate::Result<Vec<i16>> {
+        let len = self.len(2)?;
+        (0..len).map(|_| self.take().map(i16::from_le_bytes)).collect()
+    }
+
+    pub fn bytes(&mut self) -> crate::Result<&'a [u8]> {
+        let len = self.len(1)?;
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    /// Checks that the whole state was read.
+    pub fn finish(self) -> crate::Result<()> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid("trailing bytes"))
+        }
+    }
+}
+
+pub(crate) fn invalid(reason: &str) -> VadError {
+    VadError::VadError(format!("Invalid VAD state: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = Writer::default();
+        writer.usize(42);
+        writer.i64(-7);
+        writer.f32(0.25);
+        writer.bool(true);
+        writer.f32s(&[1.0, -2.0]);
+        writer.i16s(&[i16::MIN, 3]);
+        writer.bytes(b"abc");
+        let bytes = writer.into_bytes();
+
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(reader.usize().unwrap(), 42);
+        assert_eq!(reader.i64().unwrap(), -7);
+        assert_eq!(reader.f32().unwrap(), 0.25);
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.f32s().unwrap(), [1.0, -2.0]);
+        assert_eq!(reader.i16s().unwrap(), [i16::MIN, 3]);
+        assert_eq!(reader.bytes().unwrap(), b"abc");
+        reader.finish().unwrap();
+
+        // a huge length doesn't allocate, nor does truncated state panic
+        let mut reader = Reader::new(&bytes[..bytes.len() - 1]);
+        reader.usize().unwrap();
+        assert!(reader.i16s().is_err());
+        assert!(Reader::new(&bytes[..4]).usize().is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::snapshot::{invalid, Reader, Writer};
+use crate::utils::TimeStamp;
+use crate::vad_iter::VadIter;
+use lockfree_object_pool::MutexOwnedReusable;
+
+/// Version of the encoding of `VadStream::save_state`, states of other versions are rejected.
+const STATE_VERSION: u64 = 3;
+
+/// Speech boundary detected while streaming audio.
+#[derive(Debug, Clone)]
+pub enum VadEvent {
+    /// Speech started at `start` seconds from the beginning of the stream.
+    SpeechStart { start: f64 },
+    /// Speech finished, holds the whole speech interval.
+    SpeechEnd(TimeStamp),
+}
+
+/// Stateful VAD over a stream of audio chunks of arbitrary size.
+///
+/// Samples that don't fill a whole frame are buffered until the next chunk arrives, so the results don't depend on how
+/// the audio is split into chunks.
+pub struct VadStream {
+    vad: MutexOwnedReusable<VadIter>,
+    sample_rate: usize,
+    buffer: Vec<i16>,
+    samples_processed: usize,
+    speech_start_reported: Option<i64>,
+}
+
+impl VadStream {
+    pub(crate) fn new(mut vad: MutexOwnedReusable<VadIter>, sample_rate: usize) -> Self {
+        vad.reset_states();
+        Self {
+            vad,
+            sample_rate,
+            buffer: Vec::new(),
+            samples_processed: 0,
+            speech_start_reported: None,
+        }
+    }
+
+    /// Restores a stream from the state encoded by `save_state`, the VAD having the same engine and parameters as the
+    /// one of the saved stream.
+    pub(crate) fn resume(vad: MutexOwnedReusable<VadIter>, sample_rate: usize, state: &[u8]) -> crate::Result<Self> {
+        let mut stream = Self::new(vad, sample_rate);
+        let mut reader = Reader::new(state);
+        if reader.u64()? != STATE_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        stream.vad.load_state(&mut reader)?;
+        stream.buffer = reader.i16s()?;
+        stream.samples_processed = reader.usize()?;
+        stream.speech_start_reported = reader.bool()?.then_some(reader.i64()?);
+        reader.finish()?;
+        if stream.buffer.len() >= stream.vad.frame_size_samples() {
+            return Err(invalid("inconsistent stream"));
+        }
+        Ok(stream)
+    }
+
+    /// Encodes the state of the stream, including the samples buffered until the next frame, so that the stream can
+    /// be resumed by another recognizer, e.g. on another server.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.u64(STATE_VERSION);
+        self.vad.save_state(&mut writer);
+        writer.i16s(&self.buffer);
+        writer.usize(self.samples_processed);
+        writer.bool(self.speech_start_reported.is_some());
+        writer.i64(self.speech_start_reported.unwrap_or_default());
+        writer.into_bytes()
+    }
+
+    /// Feeds the next chunk of audio and returns the events detected in it.
+    pub fn push(&mut self, samples: &[i16]) -> crate::Result<Vec<VadEvent>> {
+        self.buffer.extend_from_slice(samples);
+        let frame_size = self.vad.frame_size_samples();
+        let frames_len = self.buffer.len() - self.buffer.len() % frame_size;
+
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut events = Vec::new();
+        for audio_frame in buffer[..frames_len].chunks_exact(frame_size) {
+            self.vad.process_frame(audio_frame)?;
+            self.samples_processed += frame_size;
+            self.collect_events(&mut events);
+        }
+        self.buffer = buffer[frames_len..].to_vec();
+        Ok(events)
+    }
+
+    /// Ends the stream, closing the speech still in progress.
+    pub fn finish(mut self) -> Vec<VadEvent> {
+        let mut events = Vec::new();
+        self.vad.finish(self.samples_processed + self.buffer.len());
+        self.collect_events(&mut events);
+        events
+    }
+
+    /// Number of samples received so far.
+    pub fn position(&self) -> usize {
+        self.samples_processed + self.buffer.len()
+    }
+
+    /// Reports the speeches finished since the last frame and drops them from the VAD, so that neither the memory nor
+    /// the saved state grows with the length of the stream.
+    fn collect_events(&mut self, events: &mut Vec<VadEvent>) {
+        events.extend(self.vad.take_finished_speeches().into_iter().map(VadEvent::SpeechEnd));
+
+        let speech_start = self.vad.current_speech_start();
+        if let Some(start) = speech_start.filter(|start| Some(*start) != self.speech_start_reported) {
+            events.push(VadEvent::SpeechStart {
+                start: start as f64 / self.sample_rate as f64,
+            });
+        }
+        self.speech_start_reported = speech_start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::tests::{noise, tone};
+    use crate::engine::{WebRtcMode, WebRtcVad};
+    use crate::utils::{Smoothing, VadParams};
+    use crate::Recognizer;
+
+    fn recognizer() -> Recognizer {
+        let params = VadParams {
+            smoothing: Some(Smoothing::Median(3)),
+            adaptive_window_s: Some(2.0),
+            ..Default::default()
+        };
+        Recognizer::with_engine(params, || Box::new(WebRtcVad::new(16000, WebRtcMode::default())))
+    }
+
+    #[test]
+    fn test_resume_stream() {
+        let audio = [
+            noise(16000, 300),
+            tone(8000, 8000),
+            noise(8000, 300),
+            tone(8000, 8000),
+            noise(16000, 300),
+        ]
+        .concat();
+
+        let mut stream = recognizer().stream();
+        let mut expected = stream.push(&audio).unwrap();
+        expected.extend(stream.finish());
+        assert_eq!(expected.len(), 4, "{:?}", expected);
+
+        // the state is saved in the middle of a frame and of a speech, and restored by another recognizer
+        let (first, second) = audio.split_at(20000);
+        let mut stream = recognizer().stream();
+        let mut events = stream.push(first).unwrap();
+        let state = stream.save_state();
+        drop(stream);
+        let mut stream = recognizer().resume_stream(&state).unwrap();
+        events.extend(stream.push(second).unwrap());
+        events.extend(stream.finish());
+        assert_eq!(format!("{:?}", events), format!("{:?}", expected));
+
+        assert!(recognizer().resume_stream(&state[..state.len() - 1]).is_err());
+        let other =
+            Recognizer::with_engine(VadParams::default(), || Box::new(WebRtcVad::new(16000, WebRtcMode::default())));
+        assert!(other.resume_stream(&state).is_err());
+    }
+
+    #[test]
+    fn test_saved_state_does_not_grow() {
+        let turn = [noise(8000, 300), tone(8000, 8000)].concat();
+        let mut stream = recognizer().stream();
+        let mut speeches = 0;
+        let mut state_sizes = Vec::new();
+        for _ in 0..20 {
+            let events = stream.push(&turn).unwrap();
+            speeches += events
+                .iter()
+                .filter(|event| matches!(event, super::VadEvent::SpeechEnd(_)))
+                .count();
+            state_sizes.push(stream.save_state().len());
+        }
+        assert!(speeches >= 18, "{}", speeches);
+        // the size depends on the frames in progress at the end of a turn, not on the speeches before
+        let (first, last) = state_sizes.split_at(10);
+        assert!(last.iter().max() <= first.iter().max(), "{:?}", state_sizes);
+    }
+}
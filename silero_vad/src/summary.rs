@@ -0,0 +1,100 @@
+use crate::utils::TimeStamp;
+
+/// Statistics of the speech of a whole audio. Durations are in seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpeechSummary {
+    pub audio_duration: f64,
+    pub speech_duration: f64,
+    /// Share of the audio that is speech, `0` for empty audio.
+    pub speech_ratio: f64,
+    pub segments: usize,
+    /// Longest stretch without speech, including the leading and trailing silences.
+    pub longest_silence: f64,
+    pub leading_silence: f64,
+    pub trailing_silence: f64,
+}
+
+impl SpeechSummary {
+    /// Summarizes the `speeches` detected in `samples` samples at `sample_rate`.
+    ///
+    /// The speeches may come from several channels: overlapping speeches count once, as a single segment. Speeches
+    /// padded past the edges of the audio are clipped to it.
+    pub fn new(speeches: &[TimeStamp], samples: usize, sample_rate: usize) -> Self {
+        let audio_duration = samples as f64 / sample_rate.max(1) as f64;
+        let mut spans = speeches
+            .iter()
+            .map(|speech| (speech.start.clamp(0.0, audio_duration), speech.end.clamp(0.0, audio_duration)))
+            .filter(|(start, end)| start < end)
+            .collect::<Vec<_>>();
+        spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let merged = spans
+            .into_iter()
+            .fold(Vec::<(f64, f64)>::new(), |mut acc, (start, end)| {
+                match acc.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => acc.push((start, end)),
+                }
+                acc
+            });
+
+        let speech_duration = merged.iter().map(|(start, end)| end - start).sum::<f64>();
+        let leading_silence = merged.first().map_or(audio_duration, |&(start, _)| start);
+        let trailing_silence = merged.last().map_or(audio_duration, |&(_, end)| audio_duration - end);
+        let longest_silence = merged
+            .windows(2)
+            .map(|pair| pair[1].0 - pair[0].1)
+            .fold(leading_silence.max(trailing_silence), f64::max);
+        SpeechSummary {
+            audio_duration,
+            speech_duration,
+            speech_ratio: if audio_duration > 0.0 {
+                speech_duration / audio_duration
+            } else {
+                0.0
+            },
+            segments: merged.len(),
+            longest_silence,
+            leading_silence,
+            trailing_silence,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speech(start: f64, end: f64) -> TimeStamp {
+        TimeStamp {
+            start,
+            end,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        // 10 s of audio, the second and third speeches overlap, the last one is padded past the end
+        let speeches = [speech(1.0, 2.0), speech(5.0, 6.0), speech(5.5, 7.0), speech(9.5, 10.25)];
+        let summary = SpeechSummary::new(&speeches, 160000, 16000);
+        assert_eq!(
+            summary,
+            SpeechSummary {
+                audio_duration: 10.0,
+                speech_duration: 3.5,
+                speech_ratio: 0.35,
+                segments: 3,
+                longest_silence: 3.0,
+                leading_silence: 1.0,
+                trailing_silence: 0.0,
+            }
+        );
+
+        let silence = SpeechSummary::new(&[], 8000, 16000);
+        assert_eq!(silence.segments, 0);
+        assert_eq!(silence.longest_silence, 0.5);
+        assert_eq!(silence.leading_silence, 0.5);
+        assert_eq!(silence.trailing_silence, 0.5);
+        assert_eq!(SpeechSummary::new(&[], 0, 16000).speech_ratio, 0.0);
+    }
+}
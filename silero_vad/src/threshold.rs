@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+
+/// Share of the rolling window needed before the thresholds adapt, until then they are the configured ones.
+const MIN_FILL: f32 = 0.1;
+/// Percentiles of the window estimating the probabilities of noise and of speech.
+const NOISE_PERCENTILE: f32 = 0.2;
+const SPEECH_PERCENTILE: f32 = 0.95;
+/// The speech level is kept above this so that a window of noise alone doesn't squeeze the thresholds onto the noise.
+const MIN_SPEECH_LEVEL: f32 = 0.7;
+
+/// Probabilities above `onset` start a speech, probabilities below `offset` end it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Thresholds {
+    pub onset: f32,
+    pub offset: f32,
+}
+
+/// Tracks the speech probabilities over a rolling window and maps the configured thresholds from the `[0, 1]` range
+/// onto the range between the noise and the speech levels of the window: noisy audio raises them, quiet speech lowers
+/// them.
+#[derive(Debug)]
+pub(crate) struct AdaptiveThreshold {
+    configured: Thresholds,
+    window: VecDeque<f32>,
+    window_frames: usize,
+}
+
+impl AdaptiveThreshold {
+    pub fn new(configured: Thresholds, window_frames: usize) -> Self {
+        Self {
+            configured,
+            window: VecDeque::with_capacity(window_frames),
+            window_frames: window_frames.max(1),
+        }
+    }
+
+    /// Adds the probability of the next frame and returns the thresholds to apply to it.
+    pub fn update(&mut self, speech_prob: f32) -> Thresholds {
+        if self.window.len() == self.window_frames {
+            self.window.pop_front();
+        }
+        self.window.push_back(speech_prob);
+        if (self.window.len() as f32) < self.window_frames as f32 * MIN_FILL {
+            return self.configured;
+        }
+
+        let mut sorted = self.window.iter().copied().collect::<Vec<_>>();
+        sorted.sort_by(f32::total_cmp);
+        let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+        let noise = percentile(NOISE_PERCENTILE);
+        let speech = percentile(SPEECH_PERCENTILE).max(MIN_SPEECH_LEVEL).max(noise);
+        let scale = |threshold: f32| noise + threshold * (speech - noise);
+        Thresholds {
+            onset: scale(self.configured.onset),
+            offset: scale(self.configured.offset),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.window.clear();
+    }
+
+    pub fn window(&self) -> Vec<f32> {
+        self.window.iter().copied().collect()
+    }
+
+    pub fn load_window(&mut self, window: Vec<f32>) -> crate::Result<()> {
+        if window.len() > self.window_frames {
+            return Err(crate::snapshot::invalid("window longer than configured"));
+        }
+        self.window = window.into();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIGURED: Thresholds = Thresholds {
+        onset: 0.5,
+        offset: 0.35,
+    };
+
+    #[test]
+    fn test_thresholds_follow_the_noise() {
+        let mut adaptive = AdaptiveThreshold::new(CONFIGURED, 100);
+        // not enough frames yet
+        assert_eq!(adaptive.update(0.4), CONFIGURED);
+
+        // noise scored around 0.4 with some speech at 0.95
+        let mut thresholds = CONFIGURED;
+        for i in 0..100 {
+            thresholds = adaptive.update(if i % 4 == 0 { 0.95 } else { 0.4 });
+        }
+        assert!((thresholds.onset - 0.675).abs() < 1e-6, "{:?}", thresholds);
+        assert!((thresholds.offset - 0.5925).abs() < 1e-6, "{:?}", thresholds);
+
+        // clean audio keeps the configured thresholds
+        adaptive.reset();
+        for i in 0..100 {
+            thresholds = adaptive.update(if i % 4 == 0 { 1.0 } else { 0.0 });
+        }
+        assert_eq!(thresholds, CONFIGURED);
+    }
+}
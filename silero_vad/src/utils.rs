@@ -1,3 +1,6 @@
+use crate::engine::energy_db;
+use std::ops::Range;
+
 #[derive(Debug, Clone, Copy)]
 pub enum SampleRate {
     EightKHz,
@@ -32,14 +35,42 @@ impl From<usize> for SampleRate {
     }
 }
 
+/// Smoothing of the speech probabilities over the given number of frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Smoothing {
+    MovingAverage(usize),
+    Median(usize),
+}
+
+impl Smoothing {
+    pub fn frames(self) -> usize {
+        match self {
+            Smoothing::MovingAverage(frames) | Smoothing::Median(frames) => frames,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VadParams {
     pub frame_size: usize,
     pub threshold: f32,
+    /// Probability below which a speech ends, `threshold - 0.15` (at least `0.01`) when absent.
+    pub neg_threshold: Option<f32>,
+    /// Adapts the thresholds to the speech probabilities over a rolling window of this duration, `None` keeps them
+    /// fixed.
+    pub adaptive_window_s: Option<f32>,
+    /// Smoothing of the probabilities before they are compared to the thresholds, `None` uses the raw ones.
+    pub smoothing: Option<Smoothing>,
+    /// Consecutive frames above the threshold starting a speech.
+    pub onset_frames: usize,
+    /// Consecutive frames below the negative threshold needed, besides the minimum silence, to end a speech.
+    pub offset_frames: usize,
     pub min_silence_duration_ms: usize,
     pub speech_pad_ms: usize,
     pub min_speech_duration_ms: usize,
     pub max_speech_duration_s: f32,
+    /// Shortest pause a speech longer than `max_speech_duration_s` can be split at, it is split at its longest pause.
+    pub min_silence_at_max_speech_ms: usize,
     pub sample_rate: usize,
 }
 
@@ -48,10 +79,16 @@ impl Default for VadParams {
         Self {
             frame_size: 64,
             threshold: 0.5,
+            neg_threshold: None,
+            adaptive_window_s: None,
+            smoothing: None,
+            onset_frames: 1,
+            offset_frames: 1,
             min_silence_duration_ms: 0,
             speech_pad_ms: 64,
             min_speech_duration_ms: 64,
             max_speech_duration_s: f32::INFINITY,
+            min_silence_at_max_speech_ms: 98,
             sample_rate: 16000,
         }
     }
@@ -68,6 +105,8 @@ impl FrameStamp {
         TimeStamp {
             start: self.start as f64 / sample_rate as f64,
             end: self.end as f64 / sample_rate as f64,
+            samples: Some(self.start.max(0) as usize..self.end.max(0) as usize),
+            stats: None,
         }
     }
 }
@@ -83,4 +122,57 @@ impl std::fmt::Display for FrameStamp {
 pub struct TimeStamp {
     pub start: f64,
     pub end: f64,
+    /// Exact sample indices of the speech at the sample rate of the VAD, set by the VAD.
+    pub samples: Option<Range<usize>>,
+    /// Statistics of the frames of the speech, set by the VAD.
+    pub stats: Option<SpeechStats>,
+}
+
+/// Confidence and loudness of a speech, computed over its frames.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SpeechStats {
+    pub mean_probability: f32,
+    pub max_probability: f32,
+    pub frames: usize,
+    /// RMS of the samples relative to the full scale.
+    pub rms_dbfs: f32,
+}
+
+impl SpeechStats {
+    /// Computes the statistics of frames given as their speech probability and mean power relative to the full scale.
+    pub fn from_frames(frames: impl IntoIterator<Item = (f32, f32)>) -> Option<Self> {
+        let (count, probability_sum, max_probability, power_sum) = frames.into_iter().fold(
+            (0, 0.0, 0.0f32, 0.0),
+            |(count, probability_sum, max_probability, power_sum), (probability, power)| {
+                (
+                    count + 1,
+                    probability_sum + probability,
+                    max_probability.max(probability),
+                    power_sum + power,
+                )
+            },
+        );
+        (count > 0).then(|| SpeechStats {
+            mean_probability: probability_sum / count as f32,
+            max_probability,
+            frames: count,
+            rms_dbfs: energy_db(power_sum / count as f32),
+        })
+    }
+
+    /// Statistics of the frames of both speeches.
+    pub fn merge(&self, other: &SpeechStats) -> SpeechStats {
+        let frames = self.frames + other.frames;
+        let weighted = |a: f32, b: f32| (a * self.frames as f32 + b * other.frames as f32) / frames.max(1) as f32;
+        SpeechStats {
+            mean_probability: weighted(self.mean_probability, other.mean_probability),
+            max_probability: self.max_probability.max(other.max_probability),
+            frames,
+            rms_dbfs: energy_db(weighted(dbfs_to_power(self.rms_dbfs), dbfs_to_power(other.rms_dbfs))),
+        }
+    }
+}
+
+fn dbfs_to_power(dbfs: f32) -> f32 {
+    10f32.powf(dbfs / 10.0)
 }
@@ -1,43 +1,104 @@
-use crate::utils::TimeStamp;
-use crate::{silero, utils};
+use crate::engine::VadEngine;
+use crate::engine::I16_SCALE;
+use crate::smoothing::Smoother;
+use crate::snapshot::{invalid, Reader, Writer};
+use crate::threshold::{AdaptiveThreshold, Thresholds};
+use crate::utils;
+use crate::utils::{SpeechStats, TimeStamp};
 use lazy_static::lazy_static;
+use std::collections::VecDeque;
 
 lazy_static! {
     static ref DEBUG_SPEECH_PROB: bool = std::env::var("DEBUG_SPEECH_PROB").is_ok_and(|s| s == "true");
 }
 
-#[derive(Debug)]
 pub struct VadIter {
-    silero: silero::SileroSession,
+    engine: Box<dyn VadEngine>,
     params: Params,
+    smoother: Option<Smoother>,
+    adaptive_threshold: Option<AdaptiveThreshold>,
     state: State,
 }
 
 impl VadIter {
-    pub fn new(silero: silero::SileroSession, params: utils::VadParams) -> Self {
+    pub fn new(engine: Box<dyn VadEngine>, params: utils::VadParams) -> Self {
+        let smoother = params.smoothing.map(Smoother::new);
+        let params = Params::from(params);
+        let adaptive_threshold = params
+            .adaptive_window_frames
+            .map(|window_frames| AdaptiveThreshold::new(params.thresholds, window_frames));
         Self {
-            silero,
-            state: State::new(params.sample_rate.clone()),
-            params: Params::from(params),
+            engine,
+            state: State::new(params.sample_rate),
+            params,
+            smoother,
+            adaptive_threshold,
         }
     }
 
-    pub fn process(&mut self, samples: &[i16]) -> Result<(), ort::Error> {
+    pub fn process(&mut self, samples: &[i16]) -> crate::Result<()> {
         self.reset_states();
         for audio_frame in samples.chunks_exact(self.params.frame_size_samples) {
-            let speech_prob: f32 = self.silero.calc_level(audio_frame)?;
-            self.state.update(&self.params, speech_prob);
+            self.process_frame(audio_frame)?;
         }
-        self.state.check_for_last_speech(samples.len());
+        self.state.check_for_last_speech(&self.params, samples.len());
         Ok(())
     }
 
+    pub(crate) fn process_frame(&mut self, audio_frame: &[i16]) -> crate::Result<()> {
+        let mut speech_prob: f32 = self.engine.process_frame(audio_frame)?;
+        if let Some(smoother) = &mut self.smoother {
+            speech_prob = smoother.update(speech_prob);
+        }
+        let thresholds = match &mut self.adaptive_threshold {
+            Some(adaptive_threshold) => adaptive_threshold.update(speech_prob),
+            None => self.params.thresholds,
+        };
+        let power =
+            audio_frame.iter().map(|&x| (x as f32 / I16_SCALE).powi(2)).sum::<f32>() / audio_frame.len().max(1) as f32;
+        self.state.update(&self.params, thresholds, speech_prob, power);
+        Ok(())
+    }
+
+    /// Closes the speech that is still in progress after `last_sample` samples.
+    pub(crate) fn finish(&mut self, last_sample: usize) {
+        self.state.check_for_last_speech(&self.params, last_sample);
+    }
+
+    pub(crate) fn frame_size_samples(&self) -> usize {
+        self.params.frame_size_samples
+    }
+
+    /// Start of the speech in progress, if any.
+    pub(crate) fn current_speech_start(&self) -> Option<i64> {
+        self.state.triggered.then_some(self.state.current_speech.start)
+    }
+
+    /// Speeches finished so far, without merging.
+    #[cfg(test)]
+    pub(crate) fn finished_speeches(&self) -> &[TimeStamp] {
+        &self.state.speeches
+    }
+
+    /// Takes the speeches finished since the last call, without merging, so that a stream doesn't keep them.
+    pub(crate) fn take_finished_speeches(&mut self) -> Vec<TimeStamp> {
+        std::mem::take(&mut self.state.speeches)
+    }
+
     pub fn speeches(&self) -> Vec<TimeStamp> {
         // merge timestamps if end of one speech is the same as start of another
         self.state.speeches.iter().fold(Vec::new(), |mut acc, speech| {
             if let Some(last) = acc.last_mut() {
                 if last.end == speech.start {
                     last.end = speech.end;
+                    last.samples = match (&last.samples, &speech.samples) {
+                        (Some(first), Some(second)) => Some(first.start..second.end),
+                        _ => None,
+                    };
+                    last.stats = match (last.stats, speech.stats) {
+                        (Some(first), Some(second)) => Some(first.merge(&second)),
+                        (first, second) => first.or(second),
+                    };
                     return acc;
                 }
             }
@@ -45,39 +106,64 @@ impl VadIter {
             acc
         })
     }
-    fn reset_states(&mut self) {
-        self.silero.reset();
-        self.state = State::new(self.params.sample_rate)
+    /// Encodes the state of the engine and of the speech segmentation in progress.
+    pub(crate) fn save_state(&self, writer: &mut Writer) {
+        writer.usize(self.params.sample_rate);
+        writer.usize(self.params.frame_size_samples);
+        writer.bytes(&self.engine.save_state());
+        writer.f32s(&self.smoother.as_ref().map(Smoother::window).unwrap_or_default());
+        writer.f32s(
+            &self
+                .adaptive_threshold
+                .as_ref()
+                .map(AdaptiveThreshold::window)
+                .unwrap_or_default(),
+        );
+        self.state.save(writer);
     }
-}
 
-struct VadPredictor {
-    silero: silero::SileroSession,
-    params: Params,
-}
-
-impl VadPredictor {
-    pub fn new(silero: silero::SileroSession, params: utils::VadParams) -> Self {
-        Self {
-            silero,
-            params: Params::from(params),
+    /// Restores the state encoded by `save_state`, which must come from a VAD with the same parameters.
+    pub(crate) fn load_state(&mut self, reader: &mut Reader) -> crate::Result<()> {
+        if reader.usize()? != self.params.sample_rate || reader.usize()? != self.params.frame_size_samples {
+            return Err(invalid("state of another sample rate or frame size"));
         }
+        self.engine.load_state(reader.bytes()?)?;
+        let smoother_window = reader.f32s()?;
+        match &mut self.smoother {
+            Some(smoother) => smoother.load_window(smoother_window)?,
+            None if smoother_window.is_empty() => {}
+            None => return Err(invalid("state of a VAD with smoothing")),
+        }
+        let threshold_window = reader.f32s()?;
+        match &mut self.adaptive_threshold {
+            Some(adaptive_threshold) => adaptive_threshold.load_window(threshold_window)?,
+            None if threshold_window.is_empty() => {}
+            None => return Err(invalid("state of a VAD with adaptive thresholds")),
+        }
+        self.state = State::load(reader, self.params.sample_rate)?;
+        Ok(())
     }
 
-    // pub fn predict(&mut self, samples: &[i16]) -> Result<(), ort::Error> {
-    //     let a = for audio_frame in samples.chunks_exact(self.params.frame_size_samples) {
-    //         let speech_prob: f32 = self.silero.calc_level(audio_frame)?;
-    //         speech_prob
-    //     }
-    //     Ok(())
-    // }
+    pub(crate) fn reset_states(&mut self) {
+        self.engine.reset();
+        if let Some(smoother) = &mut self.smoother {
+            smoother.reset();
+        }
+        if let Some(adaptive_threshold) = &mut self.adaptive_threshold {
+            adaptive_threshold.reset();
+        }
+        self.state = State::new(self.params.sample_rate)
+    }
 }
 
 #[allow(unused)]
 #[derive(Debug)]
 pub(crate) struct Params {
     frame_size: usize,
-    threshold: f32,
+    thresholds: Thresholds,
+    adaptive_window_frames: Option<usize>,
+    onset_frames: usize,
+    offset_frames: usize,
     min_silence_duration_ms: usize,
     speech_pad_ms: usize,
     min_speech_duration_ms: usize,
@@ -95,7 +181,10 @@ pub(crate) struct Params {
 impl From<utils::VadParams> for Params {
     fn from(value: utils::VadParams) -> Self {
         let frame_size = value.frame_size;
-        let threshold = value.threshold;
+        let thresholds = Thresholds {
+            onset: value.threshold,
+            offset: value.neg_threshold.unwrap_or((value.threshold - 0.15).max(0.01)),
+        };
         let min_silence_duration_ms = value.min_silence_duration_ms;
         let speech_pad_ms = value.speech_pad_ms;
         let min_speech_duration_ms = value.min_speech_duration_ms;
@@ -103,15 +192,21 @@ impl From<utils::VadParams> for Params {
         let sample_rate = value.sample_rate;
         let sr_per_ms = sample_rate / 1000;
         let frame_size_samples = frame_size * sr_per_ms;
+        let adaptive_window_frames = value
+            .adaptive_window_s
+            .map(|window_s| (window_s * sample_rate as f32) as usize / frame_size_samples);
         let min_speech_samples = sr_per_ms * min_speech_duration_ms;
         let speech_pad_samples = sr_per_ms * speech_pad_ms;
         let max_speech_samples =
             sample_rate as f32 * max_speech_duration_s - frame_size_samples as f32 - 2.0 * speech_pad_samples as f32;
         let min_silence_samples = sr_per_ms * min_silence_duration_ms;
-        let min_silence_samples_at_max_speech = sr_per_ms * 98;
+        let min_silence_samples_at_max_speech = sr_per_ms * value.min_silence_at_max_speech_ms;
         Self {
             frame_size,
-            threshold,
+            thresholds,
+            adaptive_window_frames,
+            onset_frames: value.onset_frames.max(1),
+            offset_frames: value.offset_frames.max(1),
             min_silence_duration_ms,
             speech_pad_ms,
             min_speech_duration_ms,
@@ -131,11 +226,19 @@ impl From<utils::VadParams> for Params {
 #[derive(Debug, Default)]
 struct State {
     current_sample: usize,
+    /// Start of the silence in the speech in progress, `0` if the last frame wasn't silent.
     temp_end: usize,
-    next_start: usize,
-    prev_end: usize,
+    /// Pauses of the speech in progress long enough to split it at, as their start and duration.
+    possible_ends: Vec<(usize, usize)>,
     triggered: bool,
+    /// Consecutive frames above the onset threshold, and below the offset one.
+    speech_frames: usize,
+    silence_frames: usize,
     current_speech: utils::FrameStamp,
+    /// Speech probability and mean power of the frames from `history_start`, the frames of the speech in progress or
+    /// that may start one.
+    history: VecDeque<(f32, f32)>,
+    history_start: usize,
     speeches: Vec<utils::TimeStamp>,
     sample_rate: usize,
 }
@@ -148,67 +251,153 @@ impl State {
         }
     }
 
-    fn update(&mut self, params: &Params, speech_prob: f32) {
+    /// Encodes the segmentation in progress. The finished speeches aren't saved, streams take them as they finish.
+    fn save(&self, writer: &mut Writer) {
+        writer.usize(self.current_sample);
+        writer.usize(self.temp_end);
+        writer.usize(self.possible_ends.len());
+        for &(end, silence) in &self.possible_ends {
+            writer.usize(end);
+            writer.usize(silence);
+        }
+        writer.bool(self.triggered);
+        writer.usize(self.speech_frames);
+        writer.usize(self.silence_frames);
+        writer.i64(self.current_speech.start);
+        writer.i64(self.current_speech.end);
+        let (probabilities, powers): (Vec<f32>, Vec<f32>) = self.history.iter().copied().unzip();
+        writer.f32s(&probabilities);
+        writer.f32s(&powers);
+        writer.usize(self.history_start);
+    }
+
+    fn load(reader: &mut Reader, sample_rate: usize) -> crate::Result<Self> {
+        let mut state = State {
+            current_sample: reader.usize()?,
+            temp_end: reader.usize()?,
+            sample_rate,
+            ..Default::default()
+        };
+        for _ in 0..reader.usize()? {
+            state.possible_ends.push((reader.usize()?, reader.usize()?));
+        }
+        state.triggered = reader.bool()?;
+        state.speech_frames = reader.usize()?;
+        state.silence_frames = reader.usize()?;
+        state.current_speech = utils::FrameStamp {
+            start: reader.i64()?,
+            end: reader.i64()?,
+        };
+        let (probabilities, powers) = (reader.f32s()?, reader.f32s()?);
+        if probabilities.len() != powers.len() {
+            return Err(invalid("frame history"));
+        }
+        state.history = probabilities.into_iter().zip(powers).collect();
+        state.history_start = reader.usize()?;
+        Ok(state)
+    }
+
+    /// Follows the segmentation of `get_speech_timestamps` of the Python Silero VAD, the positions being the starts of
+    /// the frames.
+    fn update(&mut self, params: &Params, thresholds: Thresholds, speech_prob: f32, power: f32) {
+        let frame_start = self.current_sample;
         self.current_sample += params.frame_size_samples;
 
-        if speech_prob > params.threshold {
-            self.handle_speech_start(params, speech_prob);
+        if !self.triggered {
+            // only the frames that may be the first onset frames of a speech are kept
+            while self.history.len() >= params.onset_frames {
+                self.history.pop_front();
+                self.history_start += params.frame_size_samples;
+            }
+        }
+        if self.history.is_empty() {
+            self.history_start = frame_start;
+        }
+        self.history.push_back((speech_prob, power));
+
+        let is_speech = speech_prob >= thresholds.onset;
+        if is_speech {
+            self.speech_frames += 1;
+            self.silence_frames = 0;
+        } else {
+            self.speech_frames = 0;
+            if speech_prob < thresholds.offset {
+                self.silence_frames += 1;
+            }
+        }
+
+        if is_speech && self.temp_end != 0 {
+            let silence = frame_start - self.temp_end;
+            if silence > params.min_silence_samples_at_max_speech {
+                self.possible_ends.push((self.temp_end, silence));
+            }
+            self.temp_end = 0;
+        }
+
+        if !self.triggered {
+            if is_speech && self.speech_frames >= params.onset_frames {
+                self.handle_speech_start(params, frame_start, speech_prob);
+            }
             return;
         }
 
-        if self.triggered && self.is_max_speech_duration_exceeded(params) {
-            self.handle_max_speech_duration();
+        if (frame_start as i64 - self.current_speech.start) as f32 > params.max_speech_samples
+            && !self.handle_max_speech_duration(params, frame_start)
+        {
             return;
         }
 
         #[cfg(debug_assertions)]
-        if self.is_speech_prob_near_threshold(speech_prob, params) {
+        if Self::is_speech_prob_near_threshold(speech_prob, thresholds) {
             self.debug_speech_prob(speech_prob, params);
         }
 
-        if self.triggered && speech_prob < (params.threshold - 0.15) {
-            self.handle_speech_end(params, speech_prob);
-        }
-    }
-
-    fn handle_speech_start(&mut self, params: &Params, speech_prob: f32) {
-        if self.temp_end != 0 {
-            self.temp_end = 0;
-            if self.next_start < self.prev_end {
-                self.next_start = self.current_sample.saturating_sub(params.frame_size_samples);
-            }
-        }
-        if !self.triggered {
-            #[cfg(debug_assertions)]
-            self.debug(speech_prob, params, "start");
-            self.triggered = true;
-            self.current_speech.start = self.current_sample as i64 - params.frame_size_samples as i64;
+        if speech_prob < thresholds.offset {
+            self.handle_speech_end(params, frame_start, speech_prob);
         }
     }
 
-    fn is_max_speech_duration_exceeded(&self, params: &Params) -> bool {
-        (self.current_sample as i64 - self.current_speech.start) as f32 > params.max_speech_samples
+    fn handle_speech_start(&mut self, params: &Params, frame_start: usize, speech_prob: f32) {
+        #[cfg(debug_assertions)]
+        self.debug(speech_prob, params, "start");
+        self.triggered = true;
+        // the speech starts at the first of the onset frames
+        self.current_speech.start = (frame_start - (self.speech_frames - 1) * params.frame_size_samples) as i64;
     }
 
-    fn handle_max_speech_duration(&mut self) {
-        if self.prev_end > 0 {
-            self.current_speech.end = self.prev_end as _;
-            self.take_speech();
-            if self.next_start < self.prev_end {
+    /// Splits the speech in progress at its longest pause, or cuts it at `frame_start` if it has no pause long enough.
+    /// Returns whether the speech goes on after the split.
+    fn handle_max_speech_duration(&mut self, params: &Params, frame_start: usize) -> bool {
+        // the first of the longest pauses
+        let longest_pause = self
+            .possible_ends
+            .iter()
+            .rev()
+            .max_by_key(|(_, silence)| *silence)
+            .copied();
+        self.possible_ends.clear();
+        self.temp_end = 0;
+        match longest_pause {
+            Some((end, silence)) => {
+                self.current_speech.end = end as _;
+                self.take_speech(params);
+                // the next speech starts where the speech resumed after the pause
+                self.current_speech.start = (end + silence) as _;
+                true
+            }
+            None => {
+                self.current_speech.end = frame_start as _;
+                self.take_speech(params);
                 self.triggered = false;
-            } else {
-                self.current_speech.start = self.next_start as _;
+                // the next speech starts after the cut
+                self.speech_frames = 0;
+                false
             }
-        } else {
-            self.current_speech.end = self.current_sample as _;
-            self.take_speech();
-            self.triggered = false;
         }
-        self.reset_temporary_states();
     }
 
-    fn is_speech_prob_near_threshold(&self, speech_prob: f32, params: &Params) -> bool {
-        speech_prob >= (params.threshold - 0.15) && speech_prob < params.threshold
+    fn is_speech_prob_near_threshold(speech_prob: f32, thresholds: Thresholds) -> bool {
+        speech_prob >= thresholds.offset && speech_prob < thresholds.onset
     }
 
     #[cfg(debug_assertions)]
@@ -220,43 +409,52 @@ impl State {
         }
     }
 
-    fn handle_speech_end(&mut self, params: &Params, speech_prob: f32) {
+    fn handle_speech_end(&mut self, params: &Params, frame_start: usize, speech_prob: f32) {
         #[cfg(debug_assertions)]
         self.debug(speech_prob, params, "end");
         if self.temp_end == 0 {
-            self.temp_end = self.current_sample;
+            self.temp_end = frame_start;
         }
-        if self.current_sample.saturating_sub(self.temp_end) > params.min_silence_samples_at_max_speech {
-            self.prev_end = self.temp_end;
+        if self.silence_frames < params.offset_frames {
+            return;
         }
-        if self.current_sample.saturating_sub(self.temp_end) >= params.min_silence_samples {
+        if frame_start - self.temp_end >= params.min_silence_samples {
             self.current_speech.end = self.temp_end as _;
             if self.current_speech.end - self.current_speech.start > params.min_speech_samples as _ {
-                self.take_speech();
-                self.reset_temporary_states();
-                self.triggered = false;
+                self.take_speech(params);
+            } else {
+                // too short to be a speech
+                self.current_speech = Default::default();
             }
+            self.reset_temporary_states();
+            self.triggered = false;
         }
     }
 
     fn reset_temporary_states(&mut self) {
-        self.prev_end = 0;
-        self.next_start = 0;
+        self.possible_ends.clear();
         self.temp_end = 0;
     }
 
-    fn take_speech(&mut self) {
-        let frame_stamp = std::mem::take(&mut self.current_speech);
-        self.speeches.push(frame_stamp.to_timestamp(self.sample_rate)); // current speech becomes TimeStamp::default() due to take()
+    fn take_speech(&mut self, params: &Params) {
+        let frame_stamp = std::mem::take(&mut self.current_speech); // current speech becomes TimeStamp::default() due to take()
+        let frame_index = |sample: i64| {
+            ((sample - self.history_start as i64).max(0) as usize / params.frame_size_samples).min(self.history.len())
+        };
+        let (first, last) = (frame_index(frame_stamp.start), frame_index(frame_stamp.end));
+        let mut timestamp = frame_stamp.to_timestamp(self.sample_rate);
+        timestamp.stats = SpeechStats::from_frames(self.history.range(first..last).copied());
+        self.speeches.push(timestamp);
+        // the next speech starts after the end of this one
+        self.history.drain(..last);
+        self.history_start += last * params.frame_size_samples;
     }
 
-    fn check_for_last_speech(&mut self, last_sample: usize) {
-        if self.current_speech.start > 0 {
+    fn check_for_last_speech(&mut self, params: &Params, last_sample: usize) {
+        if self.triggered && last_sample as i64 - self.current_speech.start > params.min_speech_samples as i64 {
             self.current_speech.end = last_sample as _;
-            self.take_speech();
-            self.prev_end = 0;
-            self.next_start = 0;
-            self.temp_end = 0;
+            self.take_speech(params);
+            self.reset_temporary_states();
             self.triggered = false;
         }
     }
@@ -277,3 +475,229 @@ impl State {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::ScriptedEngine;
+
+    const SPEECH: f32 = 0.9;
+    const SILENCE: f32 = 0.0;
+    /// Between the negative threshold and the threshold: neither starts nor ends a speech.
+    const UNSURE: f32 = 0.4;
+
+    /// Speeches as `(start, end)` frames.
+    type Frames = Vec<(usize, usize)>;
+
+    /// Runs the VAD on audio of as many frames as `probabilities`, returning the finished and the merged speeches in
+    /// frames.
+    fn run(vad_params: utils::VadParams, probabilities: &[f32]) -> (Frames, Frames) {
+        let frame_s = vad_params.frame_size as f64 / 1000.0;
+        let frame_size_samples = vad_params.frame_size * vad_params.sample_rate / 1000;
+        let mut vad = VadIter::new(Box::new(ScriptedEngine::new(probabilities)), vad_params);
+        vad.process(&vec![0; probabilities.len() * frame_size_samples]).unwrap();
+        let to_frames = |speeches: &[TimeStamp]| {
+            speeches
+                .iter()
+                .map(|speech| ((speech.start / frame_s).round() as usize, (speech.end / frame_s).round() as usize))
+                .collect::<Vec<_>>()
+        };
+        (to_frames(vad.finished_speeches()), to_frames(&vad.speeches()))
+    }
+
+    fn speeches(vad_params: utils::VadParams, probabilities: &[f32]) -> Frames {
+        run(vad_params, probabilities).1
+    }
+
+    #[test]
+    fn test_speech_start_and_end() {
+        let params = utils::VadParams::default;
+        // the speech ends at the first frame of silence
+        assert_eq!(
+            speeches(params(), &[SILENCE, SILENCE, SPEECH, SPEECH, SPEECH, SILENCE, SILENCE]),
+            [(2, 5)]
+        );
+        // unsure frames neither start nor end a speech
+        assert_eq!(speeches(params(), &[UNSURE, SPEECH, UNSURE, UNSURE, SPEECH, SILENCE]), [(1, 5)]);
+        // speeches at the edges of the audio
+        assert_eq!(speeches(params(), &[SPEECH, SPEECH, SPEECH]), [(0, 3)]);
+        assert_eq!(speeches(params(), &[SILENCE, SPEECH, SPEECH]), [(1, 3)]);
+        assert_eq!(speeches(params(), &[SILENCE; 5]), []);
+
+        let explicit_neg_threshold = utils::VadParams {
+            neg_threshold: Some(0.45),
+            ..params()
+        };
+        assert_eq!(speeches(explicit_neg_threshold, &[SPEECH, SPEECH, UNSURE, SILENCE]), [(0, 2)]);
+
+        // the same VAD gives the same results again
+        let mut vad = VadIter::new(Box::new(ScriptedEngine::new(&[SILENCE, SPEECH, SPEECH, SILENCE])), params());
+        for _ in 0..2 {
+            vad.process(&[0; 4 * 1024]).unwrap();
+            assert_eq!(vad.speeches().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_min_silence() {
+        let params = utils::VadParams {
+            min_silence_duration_ms: 192,
+            ..Default::default()
+        };
+        // a pause of 2 frames is bridged, the speech ends at the start of the 3 frames of silence
+        let probabilities = [
+            SPEECH, SPEECH, SILENCE, SILENCE, SPEECH, SILENCE, SILENCE, SILENCE, SILENCE, SILENCE,
+        ];
+        assert_eq!(speeches(params, &probabilities), [(0, 5)]);
+    }
+
+    #[test]
+    fn test_min_speech() {
+        let params = utils::VadParams {
+            min_speech_duration_ms: 192,
+            ..Default::default()
+        };
+        // the blip is dropped rather than growing into the following speech
+        let probabilities = [
+            SILENCE, SPEECH, SILENCE, SILENCE, SPEECH, SPEECH, SPEECH, SPEECH, SILENCE, SPEECH,
+        ];
+        assert_eq!(speeches(params.clone(), &probabilities), [(4, 8)]);
+        // a short speech at the end too
+        assert_eq!(speeches(params, &[SILENCE, SILENCE, SPEECH, SPEECH]), []);
+    }
+
+    #[test]
+    fn test_max_speech_split() {
+        // speeches longer than 9600 - 1024 samples, 8.4 frames, are split at pauses of 2 frames or more
+        let params = utils::VadParams {
+            max_speech_duration_s: 0.6,
+            speech_pad_ms: 0,
+            min_silence_duration_ms: 320,
+            min_silence_at_max_speech_ms: 64,
+            ..Default::default()
+        };
+        let (s, n) = (SPEECH, SILENCE);
+        // at the longest pause rather than the last one
+        let longest_first = [s, s, n, n, n, s, n, n, s, s, s, s, n, n, n, n, n, n];
+        assert_eq!(speeches(params.clone(), &longest_first), [(0, 2), (5, 14)]);
+        let longest_last = [s, s, n, n, s, n, n, n, s, s, s, s, n, n, n, n, n, n];
+        assert_eq!(speeches(params.clone(), &longest_last), [(0, 5), (8, 17)]);
+        // cut at the limit without a pause long enough
+        let short_pauses = [s, s, n, s, s, n, s, s, s, s, s, s, n, n, n, n, n, n];
+        assert_eq!(speeches(params, &short_pauses), [(0, 9), (10, 12)]);
+    }
+
+    #[test]
+    fn test_speech_stats() {
+        let probabilities = [SILENCE, 0.6, SPEECH, SPEECH, SILENCE, SILENCE];
+        let mut vad = VadIter::new(Box::new(ScriptedEngine::new(&probabilities)), utils::VadParams::default());
+        // half the full scale during the speech, digital silence around it
+        let samples = probabilities
+            .iter()
+            .flat_map(|&p| [if p > SILENCE { 16384 } else { 0 }; 1024])
+            .collect::<Vec<_>>();
+        vad.process(&samples).unwrap();
+
+        assert_eq!(vad.speeches()[0].samples, Some(1024..4096));
+        let stats = vad.speeches()[0].stats.unwrap();
+        assert_eq!(stats.frames, 3);
+        assert!((stats.mean_probability - 0.8).abs() < 1e-6, "{:?}", stats);
+        assert_eq!(stats.max_probability, SPEECH);
+        assert!((stats.rms_dbfs + 6.0206).abs() < 1e-3, "{:?}", stats);
+
+        // merged speeches weigh their frames
+        let merged = stats.merge(&SpeechStats {
+            mean_probability: 0.5,
+            max_probability: 0.5,
+            frames: 1,
+            rms_dbfs: -100.0,
+        });
+        assert_eq!(merged.frames, 4);
+        assert!((merged.mean_probability - 0.725).abs() < 1e-6, "{:?}", merged);
+        assert_eq!(merged.max_probability, SPEECH);
+        assert!((merged.rms_dbfs - 10.0 * (0.25f32 * 0.75).log10()).abs() < 1e-3, "{:?}", merged);
+    }
+
+    #[test]
+    fn test_merge_adjacent_speeches() {
+        let mut vad = VadIter::new(Box::new(ScriptedEngine::new(&[])), utils::VadParams::default());
+        let speech = |start, end| TimeStamp {
+            start,
+            end,
+            samples: None,
+            stats: None,
+        };
+        vad.state.speeches = vec![speech(0.0, 1.0), speech(1.0, 2.0), speech(2.5, 3.0)];
+        let merged = vad.speeches().iter().map(|s| (s.start, s.end)).collect::<Vec<_>>();
+        assert_eq!(merged, [(0.0, 2.0), (2.5, 3.0)]);
+    }
+
+    /// Segments of `get_speech_timestamps` in `testdata/golden.txt`, whose first line is the command that generated it.
+    /// The committed data comes from `python3 testdata/get_speech_timestamps.py --synthetic`, the port of
+    /// `get_speech_timestamps` in the script on a synthetic probability trace. Until it is regenerated from a recording
+    /// with the silero-vad package, the test checks the segmentation against that port, not against the package.
+    #[test]
+    fn test_golden_segments() {
+        let golden = include_str!("../testdata/golden.txt");
+        let mut lines = golden
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split_once(' ').unwrap());
+        let (_, probabilities) = lines.next().unwrap();
+        let probabilities = probabilities
+            .split(' ')
+            .map(|p| p.parse().unwrap())
+            .collect::<Vec<f32>>();
+
+        for (case, expected) in lines {
+            let params = utils::VadParams {
+                frame_size: 32,
+                threshold: 0.5,
+                min_speech_duration_ms: 250,
+                min_silence_duration_ms: 100,
+                speech_pad_ms: 0,
+                ..Default::default()
+            };
+            let params = match case {
+                "defaults" => params,
+                "max_speech" => utils::VadParams {
+                    max_speech_duration_s: 8.0,
+                    ..params
+                },
+                "max_speech_short_pauses" => utils::VadParams {
+                    max_speech_duration_s: 5.0,
+                    min_silence_duration_ms: 500,
+                    min_silence_at_max_speech_ms: 50,
+                    ..params
+                },
+                _ => panic!("unknown case {}", case),
+            };
+            let mut vad = VadIter::new(Box::new(ScriptedEngine::new(&probabilities)), params);
+            vad.process(&vec![0; probabilities.len() * 512]).unwrap();
+            let segments = vad
+                .finished_speeches()
+                .iter()
+                .map(|speech| format!("{}-{}", (speech.start * 16000.0).round(), (speech.end * 16000.0).round()))
+                .collect::<Vec<_>>();
+            assert_eq!(segments.join(" "), expected, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_onset_and_offset_frames() {
+        let probabilities = [0.0, 0.9, 0.0, 0.9, 0.9, 0.9, 0.9, 0.0, 0.9, 0.0, 0.0, 0.0, 0.0];
+
+        let flickering = utils::VadParams {
+            min_speech_duration_ms: 0,
+            ..Default::default()
+        };
+        assert_eq!(speeches(flickering, &probabilities), [(1, 2), (3, 7), (8, 9)]);
+
+        let vad_params = utils::VadParams {
+            onset_frames: 3,
+            offset_frames: 3,
+            ..Default::default()
+        };
+        assert_eq!(speeches(vad_params, &probabilities), [(3, 9)]);
+    }
+}
@@ -0,0 +1,164 @@
+"""Golden segments of the `vad_iter` tests, without the speech padding.
+
+The reference is silero-vad's own `get_speech_timestamps` on the probabilities of its model over a recording, run from
+the `silero_vad` directory with the pinned package (pip install silero-vad==6.0.0):
+
+    python3 testdata/get_speech_timestamps.py testdata/speech.wav > testdata/golden.txt
+
+Without the package or a recording, `--synthetic` runs `speech_segments`, a port of `get_speech_timestamps` (6.x,
+`use_max_poss_sil_at_max_speech=True`), on a synthetic monologue trace instead:
+
+    python3 testdata/get_speech_timestamps.py --synthetic > testdata/golden.txt
+
+The first line of the output is a comment giving the command, which the tests skip.
+"""
+
+import math
+import random
+import sys
+
+SILERO_VAD_VERSION = '6.0.0'
+SAMPLE_RATE = 16000
+WINDOW_SIZE_SAMPLES = 512
+
+CASES = [
+    ('defaults', {}),
+    ('max_speech', {'max_speech_duration_s': 8.0}),
+    ('max_speech_short_pauses', {'max_speech_duration_s': 5.0, 'min_silence_duration_ms': 500,
+                                 'min_silence_at_max_speech': 50}),
+]
+
+
+def speech_segments(speech_probs, audio_length_samples, threshold=0.5, neg_threshold=None, min_speech_duration_ms=250,
+                    max_speech_duration_s=math.inf, min_silence_duration_ms=100, min_silence_at_max_speech=98):
+    sampling_rate = SAMPLE_RATE
+    window_size_samples = WINDOW_SIZE_SAMPLES
+    speech_pad_samples = 0
+
+    min_speech_samples = sampling_rate * min_speech_duration_ms / 1000
+    max_speech_samples = sampling_rate * max_speech_duration_s - window_size_samples - 2 * speech_pad_samples
+    min_silence_samples = sampling_rate * min_silence_duration_ms / 1000
+    min_silence_samples_at_max_speech = sampling_rate * min_silence_at_max_speech / 1000
+
+    triggered = False
+    speeches = []
+    current_speech = {}
+    if neg_threshold is None:
+        neg_threshold = max(threshold - 0.15, 0.01)
+    temp_end = 0
+    prev_end = next_start = 0
+    possible_ends = []
+
+    for i, speech_prob in enumerate(speech_probs):
+        cur_sample = window_size_samples * i
+
+        if (speech_prob >= threshold) and temp_end:
+            sil_dur = cur_sample - temp_end
+            if sil_dur > min_silence_samples_at_max_speech:
+                possible_ends.append((temp_end, sil_dur))
+            temp_end = 0
+            if next_start < prev_end:
+                next_start = cur_sample
+
+        if (speech_prob >= threshold) and not triggered:
+            triggered = True
+            current_speech['start'] = cur_sample
+            continue
+
+        if triggered and (cur_sample - current_speech['start'] > max_speech_samples):
+            if possible_ends:
+                prev_end, dur = max(possible_ends, key=lambda x: x[1])
+                current_speech['end'] = prev_end
+                speeches.append(current_speech)
+                current_speech = {}
+                next_start = prev_end + dur
+                if next_start < prev_end + cur_sample:
+                    current_speech['start'] = next_start
+                else:
+                    triggered = False
+                prev_end = next_start = temp_end = 0
+                possible_ends = []
+            else:
+                current_speech['end'] = cur_sample
+                speeches.append(current_speech)
+                current_speech = {}
+                prev_end = next_start = temp_end = 0
+                triggered = False
+                possible_ends = []
+                continue
+
+        if (speech_prob < neg_threshold) and triggered:
+            if not temp_end:
+                temp_end = cur_sample
+            sil_dur_now = cur_sample - temp_end
+            if sil_dur_now < min_silence_samples:
+                continue
+            else:
+                current_speech['end'] = temp_end
+                if (current_speech['end'] - current_speech['start']) > min_speech_samples:
+                    speeches.append(current_speech)
+                current_speech = {}
+                prev_end = next_start = temp_end = 0
+                triggered = False
+                possible_ends = []
+                continue
+
+    if current_speech and (audio_length_samples - current_speech['start']) > min_speech_samples:
+        current_speech['end'] = audio_length_samples
+        speeches.append(current_speech)
+
+    return [(s['start'], s['end']) for s in speeches]
+
+
+def monologue(seed, frames):
+    """Probability trace of a monologue: talk spurts with noisy probabilities and pauses of various lengths."""
+    rng = random.Random(seed)
+    probs = []
+    while len(probs) < frames:
+        probs += [min(1.0, max(0.0, rng.gauss(0.9, 0.08))) for _ in range(rng.randint(10, 120))]
+        probs += [min(1.0, max(0.0, rng.gauss(0.1, 0.12))) for _ in range(rng.choice([1, 2, 3, 5, 8, 15, 40]))]
+    return [round(p, 2) for p in probs[:frames]]
+
+
+def synthetic():
+    print('# python3 testdata/get_speech_timestamps.py --synthetic > testdata/golden.txt: port of get_speech_timestamps'
+          ' on the synthetic monologue(7, 2000), not the silero-vad package')
+    probs = monologue(7, 2000)
+    print('probabilities', ' '.join(f'{p:.2f}' for p in probs))
+    for name, params in CASES:
+        segments = speech_segments(probs, len(probs) * WINDOW_SIZE_SAMPLES, **params)
+        print(name, ' '.join(f'{start}-{end}' for start, end in segments))
+
+
+def recording(wav_path):
+    from importlib.metadata import version
+    from silero_vad import get_speech_timestamps, load_silero_vad, read_audio
+
+    assert version('silero-vad') == SILERO_VAD_VERSION, f'silero-vad {SILERO_VAD_VERSION} is required'
+    model = load_silero_vad(onnx=True)
+    wav = read_audio(wav_path, sampling_rate=SAMPLE_RATE)
+    # whole windows only, the tests feed the probabilities back as silent frames
+    wav = wav[:len(wav) // WINDOW_SIZE_SAMPLES * WINDOW_SIZE_SAMPLES]
+
+    # the probabilities of every window, computed the way get_speech_timestamps does
+    model.reset_states()
+    probs = [model(wav[start:start + WINDOW_SIZE_SAMPLES], SAMPLE_RATE).item()
+             for start in range(0, len(wav), WINDOW_SIZE_SAMPLES)]
+
+    print(f'# python3 testdata/get_speech_timestamps.py {wav_path} > testdata/golden.txt:'
+          f' silero-vad {SILERO_VAD_VERSION}')
+    print('probabilities', ' '.join(f'{p:.9g}' for p in probs))
+    for name, params in CASES:
+        segments = get_speech_timestamps(wav, model, threshold=0.5, sampling_rate=SAMPLE_RATE,
+                                         min_speech_duration_ms=250, min_silence_duration_ms=100, speech_pad_ms=0,
+                                         **params)
+        print(name, ' '.join(f'{s["start"]}-{s["end"]}' for s in segments))
+
+
+if __name__ == '__main__':
+    if sys.argv[1:] == ['--synthetic']:
+        synthetic()
+    elif len(sys.argv) == 2:
+        recording(sys.argv[1])
+    else:
+        sys.exit(__doc__)
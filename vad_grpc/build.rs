@@ -15,7 +15,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let file_descriptor_set_path = out_dir.join("server_descriptor.bin");
 
-    let fds = protox::compile(&proto_files, &include_dirs).unwrap();
+    let fds = protox::compile(proto_files, include_dirs).unwrap();
     write_fds(&fds, &file_descriptor_set_path);
 
     let proto_gen_dir = "./src/pb";
@@ -26,6 +26,51 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .protoc_arg("--experimental_allow_proto3_optional")
         .build_server(true)
         .build_client(true)
+        // serde support for the HTTP/JSON gateway
+        .type_attribute(".vad_grpc.v1", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".vad_grpc.v1", "#[serde(default)]")
+        .field_attribute(".vad_grpc.v1.VadRequest.audio", "#[serde(with = \"crate::tools::json::base64\")]")
+        .field_attribute(".vad_grpc.v1.CompactRequest.audio", "#[serde(with = \"crate::tools::json::base64\")]")
+        .field_attribute(".vad_grpc.v1.DiarizeRequest.audio", "#[serde(with = \"crate::tools::json::base64\")]")
+        .field_attribute(".vad_grpc.v1.CompactResponse.audio", "#[serde(with = \"crate::tools::json::base64\")]")
+        .field_attribute(".vad_grpc.v1.SpeechAudio.concatenated", "#[serde(with = \"crate::tools::json::base64\")]")
+        .field_attribute(".vad_grpc.v1.SpeechAudio.segments", "#[serde(with = \"crate::tools::json::base64_list\")]")
+        .field_attribute(
+            ".vad_grpc.v1.AudioConfig.audio_type",
+            "#[serde(with = \"crate::tools::json::audio_type\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.AudioConfig.channel_mode",
+            "#[serde(with = \"crate::tools::json::channel_mode\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.AudioConfig.vad_engine",
+            "#[serde(with = \"crate::tools::json::vad_engine\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.AudioConfig.integer_timestamps",
+            "#[serde(with = \"crate::tools::json::integer_timestamps\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.CompactResponse.audio_type",
+            "#[serde(with = \"crate::tools::json::audio_type\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.AudioFormat.audio_type",
+            "#[serde(with = \"crate::tools::json::audio_type\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.SpeechAudio.audio_type",
+            "#[serde(with = \"crate::tools::json::audio_type\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.SpeechAudioOptions.mode",
+            "#[serde(with = \"crate::tools::json::speech_audio_mode\")]",
+        )
+        .field_attribute(
+            ".vad_grpc.v1.SpeechAudioOptions.format",
+            "#[serde(with = \"crate::tools::json::speech_audio_format\")]",
+        )
         .out_dir(proto_gen_dir)
         // .include_file("mod.rs")
         .compile_fds(fds)
@@ -1,10 +1,26 @@
+// the helpers fail with the `Status` returned by the service methods
+#![allow(clippy::result_large_err)]
+
 use crate::pb::vad_grpc_v1::vad_recognizer_server::VadRecognizer;
 use crate::pb::vad_grpc_v1::vad_stream_request::Content;
-use crate::pb::vad_grpc_v1::{AudioConfig, AudioType, SpeechInterval, VadRequest, VadResponse, VadStreamRequest};
+use crate::pb::vad_grpc_v1::{
+    AudioConfig, AudioFormat, AudioType, CompactRequest, CompactResponse, DiarizationConfig, DiarizeRequest,
+    DiarizeResponse, SpeechAudioMode, VadEngine, VadRequest, VadResponse, VadStreamRequest,
+};
+use crate::service::stream::StreamSession;
 use crate::settings::settings::Settings;
-use crate::tools::grpc::timestamp_to_speech_interval;
-use crate::{tools, VadService};
-use futures::{Stream, StreamExt, TryStreamExt};
+use crate::tools::channels::DecodedAudio;
+use crate::tools::grpc::{
+    channel_events_to_speech_intervals, channel_timestamps_to_speech_intervals, channel_timestamps_to_summary,
+    speaker_turns_to_speech_intervals, time_map_to_segments, timestamps_to_speech_intervals, IntegerPositions,
+};
+use crate::tools::pcm::RawEncoding;
+use crate::tools::transcode::output_audio_type;
+use crate::{tools, DiarizationService, VadService};
+use futures::{Stream, StreamExt};
+use silero_vad::diarization::SpeakerHints;
+use silero_vad::EngineKind;
+use std::borrow::Cow;
 use std::pin::Pin;
 use std::sync::Arc;
 use tonic::{Request, Response, Status, Streaming};
@@ -12,38 +28,270 @@ use vad_grpc_server::VadServiceError;
 
 pub struct VadServiceController {
     vad: Arc<VadService>,
+    diarization: Option<DiarizationService>,
 }
 
 impl VadServiceController {
     pub fn new(settings: &Settings) -> vad_grpc_server::Result<Self> {
         let vad = Arc::new(VadService::new(&settings.vad)?);
-        Ok(Self { vad })
+        let diarization = if settings.diarization.enabled {
+            Some(DiarizationService::new(&settings.diarization)?)
+        } else {
+            None
+        };
+        Ok(Self { vad, diarization })
     }
 
-    fn get_audio_and_config_from_request(request: &VadRequest) -> Result<(Vec<i16>, AudioConfig), Status> {
-        let config = request
-            .config
-            .ok_or_else(|| Status::invalid_argument("No config provided"))?;
+    fn get_audio_and_config_from_request(
+        config: Option<AudioConfig>,
+        audio: &[u8],
+    ) -> Result<(DecodedAudio<'_>, AudioConfig), Status> {
+        let config = config.ok_or_else(|| Status::invalid_argument("No config provided"))?;
 
-        let audio = Self::transform_audio_to_i16(&request.audio, &config)?;
+        let audio = Self::transform_audio_to_i16(audio, &config)?;
 
         Ok((audio, config))
     }
 
-    fn transform_audio_to_i16(audio: &[u8], config: &AudioConfig) -> Result<Vec<i16>, Status> {
-        match config.audio_type() {
-            AudioType::RawPcmS16le => Ok(tools::wav::bytes_to_i16(audio)),
-            AudioType::RawPcmS16be => {
-                let bytes = tools::transcode::pcm_s16be_to_pcm_s16le(audio);
-                Ok(tools::wav::bytes_to_i16(&bytes))
+    pub(crate) fn engine(config: &AudioConfig) -> EngineKind {
+        match config.vad_engine() {
+            VadEngine::Silero => EngineKind::Silero,
+            VadEngine::Webrtc => EngineKind::WebRtc,
+            VadEngine::Energy => EngineKind::Energy,
+        }
+    }
+
+    /// Takes the only channel of mono audio, `what` names the operation that doesn't support multi-channel audio.
+    fn single_channel<'a>(channels: &'a [Cow<'_, [i16]>], what: &str) -> Result<&'a [i16], Status> {
+        match channels {
+            [channel] => Ok(channel),
+            _ => Err(Status::invalid_argument(format!(
+                "{} is only supported for mono audio, got {} channels; select a channel or downmix with channel_mode",
+                what,
+                channels.len()
+            ))),
+        }
+    }
+
+    fn recognize_channels(
+        vad: &VadService,
+        channels: &[Cow<'_, [i16]>],
+        sample_rate: u32,
+        engine: EngineKind,
+    ) -> Result<Vec<Vec<silero_vad::TimeStamp>>, Status> {
+        channels
+            .iter()
+            .map(|samples| vad.recognize(samples, sample_rate, engine))
+            .collect::<Result<_, _>>()
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+
+    fn speaker_hints(config: Option<DiarizationConfig>) -> Result<SpeakerHints, Status> {
+        let Some(config) = config else {
+            return Ok(SpeakerHints::default());
+        };
+        if [config.num_speakers, config.min_speakers, config.max_speakers].contains(&Some(0)) {
+            return Err(Status::invalid_argument("Number of speakers must be positive"));
+        }
+        match (config.num_speakers, config.min_speakers, config.max_speakers) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => Err(Status::invalid_argument(
+                "num_speakers can't be combined with min_speakers or max_speakers",
+            )),
+            (Some(num_speakers), None, None) => Ok(SpeakerHints::exactly(num_speakers as usize)),
+            (None, Some(min), Some(max)) if min > max => {
+                Err(Status::invalid_argument("min_speakers is greater than max_speakers"))
             }
-            AudioType::WavPcmS16le => tools::wav::get_samples_from_wav(audio),
-            AudioType::Unspecified => {
-                Err(VadServiceError::InvalidAudio("Only pcm_s16le and pcm_s16be are supported".to_string()))
+            (None, min, max) => Ok(SpeakerHints {
+                min_speakers: min.map(|min| min as usize),
+                max_speakers: max.map(|max| max as usize),
+            }),
+        }
+    }
+
+    /// Frames seen and skipped by the energy gate of Silero, `None` if the cascade is disabled.
+    pub(crate) fn cascade_frames(&self) -> Option<(u64, u64)> {
+        self.vad.cascade_frames()
+    }
+
+    /// Starts a streaming session, resumed from the `resume_token` of a previous session if set.
+    pub(crate) fn start_session(
+        &self,
+        config: AudioConfig,
+        resume_token: Option<&str>,
+    ) -> Result<StreamSession, Status> {
+        StreamSession::new(self.vad.clone(), config, resume_token)
+    }
+
+    /// Decodes the audio into one vector of samples per channel, after the channel selection or downmix requested in
+    /// `config.channel_mode`, resampled to a rate supported by the model.
+    pub(crate) fn transform_audio_to_i16<'a>(
+        audio: &'a [u8],
+        config: &AudioConfig,
+    ) -> Result<DecodedAudio<'a>, Status> {
+        let raw_channels = config.channels.max(1) as usize;
+        let raw_sample_rate = config.sample_rate.max(0) as u32;
+        let audio_type = match config.audio_type() {
+            AudioType::Auto => tools::sniff::detect_audio_type(audio).ok_or_else(|| {
+                Status::invalid_argument("Unable to detect the audio format, raw audio requires audio_type")
+            })?,
+            audio_type => audio_type,
+        };
+        if let Some(encoding) = RawEncoding::from_audio_type(audio_type) {
+            return tools::pcm::decode(audio, encoding)
+                .and_then(|samples| Self::prepare_channels(samples, raw_channels, raw_sample_rate, audio_type, config))
+                .map_err(|e| Status::invalid_argument(format!("{}", e)));
+        }
+        match audio_type {
+            // the sample format of WAV audio is read from its header and must match the audio type
+            AudioType::WavPcmS16le
+            | AudioType::WavPcmF32le
+            | AudioType::WavPcmS24le
+            | AudioType::WavPcmS32le
+            | AudioType::WavPcmU8 => tools::wav::check_wav_audio_type(audio, audio_type)
+                .and_then(|_| tools::wav::get_samples_from_wav(audio))
+                .and_then(|decoded| Self::check_container_format(decoded, config)),
+            AudioType::Flac | AudioType::OggVorbis | AudioType::Mp3 => tools::compressed::decode(audio, audio_type)
+                .and_then(|decoded| Self::check_container_format(decoded, config)),
+            #[cfg(feature = "opus")]
+            AudioType::OggOpus => {
+                tools::opus::decode_ogg_opus(audio).and_then(|decoded| Self::check_container_format(decoded, config))
             }
+            #[cfg(not(feature = "opus"))]
+            AudioType::OggOpus => return Err(Self::opus_unimplemented()),
+            AudioType::OpusPackets => Err(VadServiceError::InvalidAudio(
+                "OPUS_PACKETS audio is only supported by continuous DetectStream calls".to_string(),
+            )),
+            // raw audio is decoded above, AUTO is resolved to the detected audio type
+            _ => Err(VadServiceError::InvalidAudio("Audio type is not specified".to_string())),
         }
+        .and_then(|(samples, channels, sample_rate)| {
+            Self::prepare_channels(Cow::Owned(samples), channels, sample_rate, audio_type, config)
+        })
         .map_err(|e| Status::invalid_argument(format!("{}", e)))
     }
+
+    fn check_container_format(
+        decoded: (Vec<i16>, usize, u32),
+        config: &AudioConfig,
+    ) -> vad_grpc_server::Result<(Vec<i16>, usize, u32)> {
+        let (_, channels, sample_rate) = decoded;
+        Self::check_format(channels, sample_rate, config)?;
+        Ok(decoded)
+    }
+
+    /// The sample rate and channels of a container are optional in `config`, but must match the container if set.
+    pub(crate) fn check_format(channels: usize, sample_rate: u32, config: &AudioConfig) -> vad_grpc_server::Result<()> {
+        if config.sample_rate > 0 && config.sample_rate as u32 != sample_rate {
+            return Err(VadServiceError::InvalidAudio(format!(
+                "Sample rate {} doesn't match the sample rate of the audio file, {}",
+                config.sample_rate, sample_rate
+            )));
+        }
+        if config.channels > 0 && config.channels as usize != channels {
+            return Err(VadServiceError::InvalidAudio(format!(
+                "Channels {} don't match the channels of the audio file, {}",
+                config.channels, channels
+            )));
+        }
+        Ok(())
+    }
+
+    /// Splits the interleaved samples into channels, applies `config.channel_mode` and resamples to the model rate.
+    fn prepare_channels<'a>(
+        samples: Cow<'a, [i16]>,
+        channels: usize,
+        sample_rate: u32,
+        audio_type: AudioType,
+        config: &AudioConfig,
+    ) -> vad_grpc_server::Result<DecodedAudio<'a>> {
+        let input_format = AudioFormat {
+            audio_type: audio_type as i32,
+            sample_rate,
+            channels: channels as u32,
+        };
+        let channels = Self::split_channels(samples, &input_format, config)?;
+        let model_sample_rate = tools::resample::model_sample_rate(sample_rate);
+        if model_sample_rate == sample_rate {
+            return Ok(DecodedAudio {
+                channels,
+                sample_rate,
+                input_rate_channels: None,
+                input_format,
+            });
+        }
+        let resampled = tools::resample::resample(&channels, sample_rate, model_sample_rate)?;
+        Ok(DecodedAudio {
+            channels: resampled.into_iter().map(Cow::Owned).collect(),
+            sample_rate: model_sample_rate,
+            input_rate_channels: Some(channels),
+            input_format,
+        })
+    }
+
+    /// Splits the interleaved samples of `format` into channels and applies `config.channel_mode`.
+    pub(crate) fn split_channels<'a>(
+        samples: Cow<'a, [i16]>,
+        format: &AudioFormat,
+        config: &AudioConfig,
+    ) -> vad_grpc_server::Result<Vec<Cow<'a, [i16]>>> {
+        if format.sample_rate == 0 {
+            return Err(VadServiceError::InvalidAudio("Sample rate is not specified".to_string()));
+        }
+        let channels = tools::channels::deinterleave(samples, format.channels as usize)?;
+        tools::channels::apply_channel_mode(
+            channels,
+            config.channel_mode(),
+            config.selected_channel as usize,
+            format.sample_rate as usize,
+        )
+    }
+
+    /// Runs the VAD on a chunk of a `DetectStream` call that isn't continuous, the chunk being a whole audio.
+    fn detect_message(
+        vad: &VadService,
+        config: &AudioConfig,
+        message: VadStreamRequest,
+    ) -> Result<VadResponse, Status> {
+        let chunk = match message.content {
+            Some(Content::Audio(audio)) => audio,
+            _ => return Err(Status::invalid_argument("Audio message expected")),
+        };
+        let decoded = Self::transform_audio_to_i16(&chunk.audio, config)?;
+        let results = Self::recognize_channels(vad, &decoded.channels, decoded.sample_rate, Self::engine(config))?;
+        let samples = decoded.channels.first().map_or(0, |channel| channel.len());
+        Ok(VadResponse {
+            intervals: channel_timestamps_to_speech_intervals(&results, IntegerPositions::new(config, &decoded)),
+            request_id: Some(chunk.request_id),
+            summary: Some(channel_timestamps_to_summary(&results, samples, decoded.sample_rate)),
+            input_format: Some(decoded.input_format),
+            ..Default::default()
+        })
+    }
+
+    /// Runs the VAD on a chunk of a continuous `DetectStream` call.
+    fn detect_chunk(
+        session: &mut StreamSession,
+        message: VadStreamRequest,
+        resumable: bool,
+    ) -> Result<VadResponse, Status> {
+        let chunk = match message.content {
+            Some(Content::Audio(audio)) => audio,
+            _ => return Err(Status::invalid_argument("Audio message expected")),
+        };
+        let events = session.push(&chunk.audio, &chunk.opus_packets)?;
+        Ok(VadResponse {
+            intervals: channel_events_to_speech_intervals(events, session.positions()),
+            request_id: Some(chunk.request_id),
+            input_format: session.input_format(),
+            resume_token: resumable.then(|| session.resume_token()),
+            ..Default::default()
+        })
+    }
+
+    #[cfg(not(feature = "opus"))]
+    pub(crate) fn opus_unimplemented() -> Status {
+        Status::unimplemented("The server is built without Opus support")
+    }
 }
 
 #[tonic::async_trait]
@@ -52,16 +300,35 @@ impl VadRecognizer for VadServiceController {
         let request = request.into_inner();
         // transform request.audio, which is a Vec<u8>, into a Vec<i16> by union 2 bytes into 1 float
 
-        let (audio, config) = Self::get_audio_and_config_from_request(&request)?;
+        let (decoded, config) = Self::get_audio_and_config_from_request(request.config, &request.audio)?;
 
-        let result = self
-            .vad
-            .recognize(audio, config.sample_rate as u32)
-            .map_err(|e| Status::internal(e.to_string()))?;
-        let intervals = result.iter().map(timestamp_to_speech_interval).collect();
+        let results =
+            Self::recognize_channels(&self.vad, &decoded.channels, decoded.sample_rate, Self::engine(&config))?;
+        let intervals = channel_timestamps_to_speech_intervals(&results, IntegerPositions::new(&config, &decoded));
+        let samples = decoded.channels.first().map_or(0, |channel| channel.len());
+        let summary = channel_timestamps_to_summary(&results, samples, decoded.sample_rate);
+        let speech_audio = match &request.speech_audio {
+            Some(options) if options.mode() != SpeechAudioMode::None => {
+                // the speech is cut from the audio at the input rate, the timestamps are in seconds
+                let audio = Self::single_channel(decoded.input_channels(), "Speech audio")?;
+                tools::speech_audio::speech_audio(
+                    audio,
+                    decoded.input_format.sample_rate,
+                    &results[0],
+                    decoded.input_format.audio_type(),
+                    options,
+                )
+                .map_err(|e| Status::internal(e.to_string()))?
+            }
+            _ => None,
+        };
         let response = VadResponse {
             intervals,
             request_id: None,
+            speech_audio,
+            input_format: Some(decoded.input_format),
+            summary: Some(summary),
+            resume_token: None,
         };
         Ok(Response::new(response))
     }
@@ -77,34 +344,119 @@ impl VadRecognizer for VadServiceController {
         let first_message = stream
             .message()
             .await?
-            .and_then(|m| m.content)
             .ok_or_else(|| Status::invalid_argument("No messages in stream"))?;
-        let config = match first_message {
-            Content::Config(config) => Ok(config),
-            Content::Audio(_) => Err(Status::invalid_argument("First message must be config")),
+        let config = match first_message.content {
+            Some(Content::Config(config)) => Ok(config),
+            Some(Content::Audio(_)) => Err(Status::invalid_argument("First message must be config")),
+            None => Err(Status::invalid_argument("No messages in stream")),
         }?;
 
-        let vad = self.vad.clone();
-
-        let response = stream.map(move |message| {
-            let chunk = match message?.content {
-                Some(Content::Audio(audio)) => audio,
-                _ => return Err(Status::invalid_argument("Audio message expected")),
-            };
-
-            let audio = Self::transform_audio_to_i16(&chunk.audio, &config)?;
-
-            let result = vad
-                .recognize(audio, config.sample_rate as u32)
-                .map_err(|e| Status::internal(e.to_string()))?;
-            let intervals = result.into_iter().map(|ts| timestamp_to_speech_interval(&ts)).collect();
-            let response = VadResponse {
-                intervals,
-                request_id: Some(chunk.request_id),
-            };
-            Ok(response)
+        let resumable = first_message.resumable;
+        let resuming = first_message.resume_token.is_some();
+        if !first_message.continuous {
+            if resumable || resuming || config.audio_type() == AudioType::OpusPackets {
+                return Err(Status::invalid_argument(
+                    "Resumable and OPUS_PACKETS streams must be continuous, set continuous in the first request",
+                ));
+            }
+            // every chunk is a whole audio, analysed on its own
+            let vad = self.vad.clone();
+            let response = stream.map(move |message| Self::detect_message(&vad, &config, message?));
+            return Ok(Response::new(Box::pin(response) as Self::DetectStreamStream));
+        }
+        if config.audio_type() == AudioType::OpusPackets && (resumable || resuming) {
+            // the state of the Opus decoder can't be saved in a resume token
+            return Err(Status::invalid_argument("OPUS_PACKETS streams can't be resumed"));
+        }
+        let session = self.start_session(config, first_message.resume_token.as_deref())?;
+
+        // every chunk gets the speeches finished in it, the end of the stream the speech in progress and the summary;
+        // the response stream ends after an error
+        let response = futures::stream::unfold(Some((stream, session)), move |state| async move {
+            let (mut stream, mut session) = state?;
+            match stream.message().await {
+                Ok(Some(message)) => match Self::detect_chunk(&mut session, message, resumable) {
+                    Ok(response) => Some((Ok(response), Some((stream, session)))),
+                    Err(status) => Some((Err(status), None)),
+                },
+                Ok(None) => {
+                    let positions = session.positions();
+                    let input_format = session.input_format();
+                    let response = session.finish().map(|(events, summary)| VadResponse {
+                        intervals: channel_events_to_speech_intervals(events, positions),
+                        input_format,
+                        summary: Some(summary),
+                        ..Default::default()
+                    });
+                    Some((response, None))
+                }
+                Err(status) => Some((Err(status), None)),
+            }
         });
 
         Ok(Response::new(Box::pin(response) as Self::DetectStreamStream))
     }
+
+    async fn compact(&self, request: Request<CompactRequest>) -> Result<Response<CompactResponse>, Status> {
+        let request = request.into_inner();
+
+        let (decoded, config) = Self::get_audio_and_config_from_request(request.config, &request.audio)?;
+        let sample_rate = decoded.sample_rate;
+        let positions = IntegerPositions::new(&config, &decoded);
+        let audio = Self::single_channel(&decoded.channels, "Compact")?;
+
+        let result = self
+            .vad
+            .recognize(audio, sample_rate, Self::engine(&config))
+            .map_err(|e| Status::internal(e.to_string()))?;
+        // the audio is compacted at the input rate, the timestamps are in seconds
+        let input_audio = Self::single_channel(decoded.input_channels(), "Compact")?;
+        let input_sample_rate = decoded.input_format.sample_rate;
+        let compacted = silero_vad::compact::compact(
+            input_audio,
+            &result,
+            input_sample_rate as usize,
+            request.max_silence_ms as usize,
+        );
+        let audio_type = output_audio_type(decoded.input_format.audio_type());
+        let compacted_audio = tools::transcode::encode_samples(&compacted.samples, input_sample_rate, audio_type)
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let response = CompactResponse {
+            audio_type: audio_type as i32,
+            audio: compacted_audio,
+            time_map: time_map_to_segments(&compacted.time_map, input_sample_rate),
+            intervals: timestamps_to_speech_intervals(&result, positions),
+            input_format: Some(decoded.input_format),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn diarize(&self, request: Request<DiarizeRequest>) -> Result<Response<DiarizeResponse>, Status> {
+        let diarization = self
+            .diarization
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("Diarization is disabled"))?;
+        let request = request.into_inner();
+        let hints = Self::speaker_hints(request.diarization_config)?;
+
+        let (decoded, config) = Self::get_audio_and_config_from_request(request.config, &request.audio)?;
+        let sample_rate = decoded.sample_rate;
+        let audio = Self::single_channel(&decoded.channels, "Diarization")?;
+
+        let result = self
+            .vad
+            .recognize(audio, sample_rate, Self::engine(&config))
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let turns = diarization
+            .diarize(audio, sample_rate, &result, hints)
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let response = DiarizeResponse {
+            num_speakers: turns.iter().map(|turn| turn.speaker + 1).max().unwrap_or(0) as u32,
+            intervals: speaker_turns_to_speech_intervals(&turns),
+            input_format: Some(decoded.input_format),
+        };
+        Ok(Response::new(response))
+    }
 }
@@ -0,0 +1,189 @@
+//! Minimal gRPC-Web (https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md) support for unary calls, in both
+//! the binary (`application/grpc-web+proto`) and the text (`application/grpc-web-text+proto`) encodings.
+
+use crate::controller::VadServiceController;
+use crate::pb::vad_grpc_v1::vad_recognizer_server::VadRecognizer;
+use crate::pb::vad_grpc_v1::{CompactRequest, DiarizeRequest, VadRequest};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use prost::Message;
+use std::future::Future;
+use std::sync::Arc;
+use tonic::Status;
+
+const FRAME_HEADER_SIZE: usize = 5;
+const TRAILERS_FLAG: u8 = 0x80;
+const COMPRESSED_FLAG: u8 = 0x01;
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Binary,
+    Text,
+}
+
+impl Encoding {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        if content_type.starts_with("application/grpc-web-text") {
+            Some(Encoding::Text)
+        } else if content_type.starts_with("application/grpc-web") {
+            Some(Encoding::Binary)
+        } else {
+            None
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Binary => "application/grpc-web+proto",
+            Encoding::Text => "application/grpc-web-text+proto",
+        }
+    }
+}
+
+fn frame(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.push(flag);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[allow(clippy::result_large_err)]
+fn decode_message<M: Message + Default>(encoding: Encoding, body: &[u8]) -> Result<M, Status> {
+    let body = match encoding {
+        Encoding::Binary => body.to_vec(),
+        Encoding::Text => base64::engine::general_purpose::STANDARD
+            .decode(body)
+            .map_err(|e| Status::invalid_argument(format!("Invalid base64 body: {}", e)))?,
+    };
+    if body.len() < FRAME_HEADER_SIZE {
+        return Err(Status::invalid_argument("Incomplete gRPC-Web frame"));
+    }
+    if body[0] & COMPRESSED_FLAG != 0 {
+        return Err(Status::unimplemented("Compressed gRPC-Web messages are not supported"));
+    }
+    let length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let message = body
+        .get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + length)
+        .ok_or_else(|| Status::invalid_argument("Incomplete gRPC-Web frame"))?;
+    M::decode(message).map_err(|e| Status::invalid_argument(format!("Failed to decode message: {}", e)))
+}
+
+fn encode_response<M: Message>(encoding: Encoding, result: Result<M, Status>) -> Response {
+    let (mut body, status) = match result {
+        Ok(message) => (frame(0, &message.encode_to_vec()), Status::ok("")),
+        Err(status) => (Vec::new(), status),
+    };
+    let trailers = format!("grpc-status:{}\r\ngrpc-message:{}\r\n", status.code() as i32, status.message());
+    body.extend(frame(TRAILERS_FLAG, trailers.as_bytes()));
+
+    let body = match encoding {
+        Encoding::Binary => body,
+        Encoding::Text => base64::engine::general_purpose::STANDARD.encode(body).into_bytes(),
+    };
+    ([(header::CONTENT_TYPE, HeaderValue::from_static(encoding.content_type()))], body).into_response()
+}
+
+async fn unary<Req, Resp, F, Fut>(headers: HeaderMap, body: Bytes, call: F) -> Response
+where
+    Req: Message + Default,
+    Resp: Message,
+    F: FnOnce(Req) -> Fut,
+    Fut: Future<Output = Result<tonic::Response<Resp>, Status>>,
+{
+    let Some(encoding) = Encoding::from_headers(&headers) else {
+        return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+    };
+    let result = match decode_message::<Req>(encoding, &body) {
+        Ok(request) => call(request).await.map(tonic::Response::into_inner),
+        Err(status) => Err(status),
+    };
+    encode_response(encoding, result)
+}
+
+/// `POST /vad_grpc.v1.VadRecognizer/Detect`
+pub async fn detect(State(controller): State<Arc<VadServiceController>>, headers: HeaderMap, body: Bytes) -> Response {
+    unary(headers, body, |request: VadRequest| async move {
+        controller.detect(tonic::Request::new(request)).await
+    })
+    .await
+}
+
+/// `POST /vad_grpc.v1.VadRecognizer/Compact`
+pub async fn compact(State(controller): State<Arc<VadServiceController>>, headers: HeaderMap, body: Bytes) -> Response {
+    unary(headers, body, |request: CompactRequest| async move {
+        controller.compact(tonic::Request::new(request)).await
+    })
+    .await
+}
+
+/// `POST /vad_grpc.v1.VadRecognizer/Diarize`
+pub async fn diarize(State(controller): State<Arc<VadServiceController>>, headers: HeaderMap, body: Bytes) -> Response {
+    unary(headers, body, |request: DiarizeRequest| async move {
+        controller.diarize(tonic::Request::new(request)).await
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::vad_grpc_v1::{AudioConfig, AudioType, SpeechInterval, VadResponse};
+
+    fn request() -> VadRequest {
+        VadRequest {
+            config: Some(AudioConfig {
+                sample_rate: 16000,
+                audio_type: AudioType::RawPcmS16le as i32,
+                channels: 1,
+                ..Default::default()
+            }),
+            audio: vec![1, 2, 3, 4],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_message() {
+        let body = frame(0, &request().encode_to_vec());
+        let decoded: VadRequest = decode_message(Encoding::Binary, &body).unwrap();
+        assert_eq!(decoded, request());
+
+        let body = base64::engine::general_purpose::STANDARD.encode(&body);
+        let decoded: VadRequest = decode_message(Encoding::Text, body.as_bytes()).unwrap();
+        assert_eq!(decoded, request());
+
+        let status = decode_message::<VadRequest>(Encoding::Binary, &body.as_bytes()[..3]).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_encode_response() {
+        let message = VadResponse {
+            request_id: None,
+            intervals: vec![SpeechInterval {
+                start_s: 0.5,
+                end_s: 1.5,
+                speaker_id: Some(1),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let response = encode_response(Encoding::Binary, Ok(message.clone()));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        assert_eq!(body[0], 0);
+        assert_eq!(VadResponse::decode(&body[5..5 + length]).unwrap(), message);
+
+        let trailers = &body[5 + length..];
+        assert_eq!(trailers[0], TRAILERS_FLAG);
+        assert!(std::str::from_utf8(&trailers[5..])
+            .unwrap()
+            .starts_with("grpc-status:0\r\n"));
+    }
+}
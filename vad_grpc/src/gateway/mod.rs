@@ -0,0 +1,53 @@
+//! HTTP/1.1 gateway exposing the `VadRecognizer` service to clients that can't speak native gRPC:
+//! gRPC-Web for browser tooling, a REST/JSON API for everything else and a WebSocket endpoint for real-time streaming.
+
+pub(crate) mod grpc_web;
+pub(crate) mod rest;
+pub(crate) mod websocket;
+
+use crate::controller::VadServiceController;
+use axum::extract::DefaultBodyLimit;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::Router;
+use std::sync::Arc;
+use tonic::Code;
+use tower_http::cors::CorsLayer;
+
+pub fn router(controller: Arc<VadServiceController>) -> Router {
+    Router::new()
+        .route("/v1/detect", post(rest::detect))
+        .route("/v1/compact", post(rest::compact))
+        .route("/v1/diarize", post(rest::diarize))
+        .route("/v1/stream", get(websocket::stream))
+        .route("/v1/metrics", get(rest::metrics))
+        .route("/vad_grpc.v1.VadRecognizer/Detect", post(grpc_web::detect))
+        .route("/vad_grpc.v1.VadRecognizer/Compact", post(grpc_web::compact))
+        .route("/vad_grpc.v1.VadRecognizer/Diarize", post(grpc_web::diarize))
+        .layer(DefaultBodyLimit::max(crate::MAX_DECODING_MESSAGE_SIZE))
+        .layer(CorsLayer::permissive())
+        .with_state(controller)
+}
+
+pub async fn serve(addr: &str, controller: Arc<VadServiceController>) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(controller)).await
+}
+
+/// Maps a gRPC status code to the closest HTTP status code, following the mapping used by the gRPC gateways.
+pub(crate) fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::REQUEST_TIMEOUT,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
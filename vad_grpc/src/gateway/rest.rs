@@ -0,0 +1,254 @@
+use crate::controller::VadServiceController;
+use crate::pb::vad_grpc_v1::vad_recognizer_server::VadRecognizer;
+use crate::pb::vad_grpc_v1::{
+    AudioConfig, CompactRequest, DiarizationConfig, DiarizeRequest, SpeechAudioMode, SpeechAudioOptions, VadRequest,
+};
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tonic::Status;
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: i32,
+    status: String,
+    message: String,
+}
+
+/// Counters of the energy gate skipping Silero on silent frames, all zero if the cascade is disabled.
+#[derive(Serialize)]
+pub struct CascadeMetrics {
+    enabled: bool,
+    frames: u64,
+    skipped_frames: u64,
+    skipped_fraction: f64,
+}
+
+#[derive(Serialize)]
+pub struct Metrics {
+    cascade: CascadeMetrics,
+}
+
+/// Query parameters of `POST /v1/detect` besides the `AudioConfig` fields, the `SpeechAudioOptions` of a raw audio
+/// body.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct DetectQuery {
+    #[serde(with = "crate::tools::json::speech_audio_mode")]
+    speech_audio_mode: i32,
+    #[serde(with = "crate::tools::json::speech_audio_format")]
+    speech_audio_format: i32,
+}
+
+impl DetectQuery {
+    fn request(&self, config: AudioConfig, audio: Vec<u8>) -> VadRequest {
+        let speech_audio = SpeechAudioOptions {
+            mode: self.speech_audio_mode,
+            format: self.speech_audio_format,
+        };
+        VadRequest {
+            config: Some(config),
+            audio,
+            speech_audio: (speech_audio.mode() != SpeechAudioMode::None).then_some(speech_audio),
+        }
+    }
+}
+
+/// Query parameters of `POST /v1/compact` besides the `AudioConfig` fields.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct CompactQuery {
+    max_silence_ms: u32,
+}
+
+fn error_response(status: Status) -> Response {
+    let body = ErrorBody {
+        code: status.code() as i32,
+        status: format!("{:?}", status.code()),
+        message: status.message().to_string(),
+    };
+    (super::http_status(status.code()), Json(body)).into_response()
+}
+
+fn json_response<T: Serialize>(result: Result<tonic::Response<T>, Status>) -> Response {
+    match result {
+        Ok(response) => Json(response.into_inner()).into_response(),
+        Err(status) => error_response(status),
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// Parses the request either from a JSON body (`{"config": {...}, "audio": "<base64>", ...}`) or, for a raw audio
+/// body, builds it with `from_raw` from the audio and the `AudioConfig` fields passed as query parameters.
+#[allow(clippy::result_large_err)]
+fn parse_request<T: DeserializeOwned>(
+    config: AudioConfig,
+    headers: &HeaderMap,
+    body: Bytes,
+    from_raw: impl FnOnce(AudioConfig, Vec<u8>) -> T,
+) -> Result<T, Status> {
+    if is_json(headers) {
+        serde_json::from_slice(&body).map_err(|e| Status::invalid_argument(format!("Invalid JSON body: {}", e)))
+    } else {
+        Ok(from_raw(config, body.to_vec()))
+    }
+}
+
+/// `POST /v1/detect`, the speech audio of a raw audio body is requested with the `speech_audio_mode` and
+/// `speech_audio_format` query parameters.
+pub async fn detect(
+    State(controller): State<Arc<VadServiceController>>,
+    Query(config): Query<AudioConfig>,
+    Query(query): Query<DetectQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = parse_request(config, &headers, body, |config, audio| query.request(config, audio));
+    match request {
+        Ok(request) => json_response(controller.detect(tonic::Request::new(request)).await),
+        Err(status) => error_response(status),
+    }
+}
+
+/// `POST /v1/compact`
+pub async fn compact(
+    State(controller): State<Arc<VadServiceController>>,
+    Query(config): Query<AudioConfig>,
+    Query(query): Query<CompactQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = parse_request(config, &headers, body, |config, audio| CompactRequest {
+        config: Some(config),
+        audio,
+        max_silence_ms: query.max_silence_ms,
+    });
+    match request {
+        Ok(request) => json_response(controller.compact(tonic::Request::new(request)).await),
+        Err(status) => error_response(status),
+    }
+}
+
+/// `POST /v1/diarize`, the speaker hints of a raw audio body are passed as `DiarizationConfig` query parameters.
+pub async fn diarize(
+    State(controller): State<Arc<VadServiceController>>,
+    Query(config): Query<AudioConfig>,
+    Query(diarization_config): Query<DiarizationConfig>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = parse_request(config, &headers, body, |config, audio| DiarizeRequest {
+        config: Some(config),
+        audio,
+        diarization_config: Some(diarization_config),
+    });
+    match request {
+        Ok(request) => json_response(controller.diarize(tonic::Request::new(request)).await),
+        Err(status) => error_response(status),
+    }
+}
+
+/// `GET /v1/metrics`
+pub async fn metrics(State(controller): State<Arc<VadServiceController>>) -> Json<Metrics> {
+    let cascade_frames = controller.cascade_frames();
+    let (frames, skipped_frames) = cascade_frames.unwrap_or_default();
+    Json(Metrics {
+        cascade: CascadeMetrics {
+            enabled: cascade_frames.is_some(),
+            frames,
+            skipped_frames,
+            skipped_fraction: if frames == 0 {
+                0.0
+            } else {
+                skipped_frames as f64 / frames as f64
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::vad_grpc_v1::{AudioType, ChannelMode, SpeechAudioFormat};
+    use axum::http::{HeaderValue, StatusCode, Uri};
+
+    fn raw_request(config: AudioConfig, audio: Vec<u8>) -> VadRequest {
+        VadRequest {
+            config: Some(config),
+            audio,
+            speech_audio: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_json_request() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json; charset=utf-8"));
+        let body =
+            r#"{"config": {"sample_rate": 8000, "audio_type": "RAW_PCM_S16LE", "channel_mode": 2}, "audio": "AQID"}"#;
+
+        let request = parse_request(AudioConfig::default(), &headers, Bytes::from(body), raw_request).unwrap();
+        let config = request.config.unwrap();
+        assert_eq!(config.sample_rate, 8000);
+        assert_eq!(config.audio_type(), AudioType::RawPcmS16le);
+        assert_eq!(config.channel_mode(), ChannelMode::DownmixAverage);
+        assert_eq!(request.audio, [1, 2, 3]);
+
+        let status = parse_request(AudioConfig::default(), &headers, Bytes::from("{"), raw_request).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_parse_raw_request() {
+        let config = AudioConfig {
+            sample_rate: 16000,
+            ..Default::default()
+        };
+        let request = parse_request(config, &HeaderMap::new(), Bytes::from_static(&[1, 2]), raw_request).unwrap();
+        assert_eq!(request, raw_request(config, vec![1, 2]));
+    }
+
+    #[test]
+    fn test_parse_raw_request_with_speech_audio() {
+        let uri = Uri::from_static(
+            "/v1/detect?sample_rate=16000&speech_audio_mode=SPEECH_AUDIO_MODE_CONCATENATED&speech_audio_format=1",
+        );
+        let Query(config) = Query::<AudioConfig>::try_from_uri(&uri).unwrap();
+        let Query(query) = Query::<DetectQuery>::try_from_uri(&uri).unwrap();
+        let request = parse_request(config, &HeaderMap::new(), Bytes::from_static(&[1, 2]), |config, audio| {
+            query.request(config, audio)
+        })
+        .unwrap();
+        assert_eq!(config.sample_rate, 16000);
+        let speech_audio = request.speech_audio.unwrap();
+        assert_eq!(speech_audio.mode(), SpeechAudioMode::Concatenated);
+        assert_eq!(speech_audio.format(), SpeechAudioFormat::Wav);
+
+        let Query(query) =
+            Query::<DetectQuery>::try_from_uri(&Uri::from_static("/v1/detect?sample_rate=16000")).unwrap();
+        assert_eq!(query.request(config, vec![1, 2]), raw_request(config, vec![1, 2]));
+        assert!(Query::<DetectQuery>::try_from_uri(&Uri::from_static("/v1/detect?speech_audio_mode=ALL")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_error_response() {
+        let response = error_response(Status::invalid_argument("No config provided"));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], 3);
+        assert_eq!(body["status"], "InvalidArgument");
+        assert_eq!(body["message"], "No config provided");
+    }
+}
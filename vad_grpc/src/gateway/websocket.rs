@@ -0,0 +1,174 @@
+//! WebSocket streaming endpoint for real-time VAD.
+//!
+//! Protocol:
+//! - the first client message is a text frame with the `AudioConfig` as JSON, optionally with `"resumable": true`
+//!   and the `resume_token` of a previous session;
+//! - subsequent binary frames carry audio in the configured format. The header of WAV audio comes in the first frame
+//!   only, later frames continue its samples; every frame of FLAC, Ogg or MP3 audio is a complete file;
+//! - a `{"command": "finish"}` text frame flushes the speech in progress and closes the session.
+//!
+//! The server pushes `speech_start` and `speech_end` events as JSON text frames, and an `error` event before closing
+//! the connection on failure. Every channel of multi-channel audio is analysed separately and its events carry the
+//! `channel` index.
+//!
+//! Resumable sessions get a `resume_token` event after the events of every audio frame. A client reconnecting, to
+//! this server or another one, sends the last token it received with the same config and then the audio sent after
+//! the frame of that token, and the session continues as if the connection had never dropped. The audio of a resumed
+//! WAV session continues without a header.
+
+use crate::controller::VadServiceController;
+use crate::pb::vad_grpc_v1::{AudioConfig, AudioType, SpeechInterval};
+use crate::tools::grpc::{timestamp_to_speech_interval, IntegerPositions};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use silero_vad::VadEvent;
+use std::sync::Arc;
+use tonic::Status;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ServerMessage {
+    SpeechStart {
+        start_s: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<u32>,
+    },
+    SpeechEnd {
+        #[serde(flatten)]
+        interval: SpeechInterval,
+    },
+    ResumeToken {
+        token: String,
+    },
+    Error {
+        code: i32,
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn from_event(event: VadEvent, channel: Option<u32>, positions: IntegerPositions) -> Self {
+        match event {
+            VadEvent::SpeechStart { start } => ServerMessage::SpeechStart {
+                start_s: start,
+                channel,
+            },
+            VadEvent::SpeechEnd(timestamp) => ServerMessage::SpeechEnd {
+                interval: SpeechInterval {
+                    channel,
+                    ..timestamp_to_speech_interval(&timestamp, positions)
+                },
+            },
+        }
+    }
+}
+
+/// The first message of a session.
+#[derive(Deserialize)]
+struct SessionConfig {
+    #[serde(flatten)]
+    config: AudioConfig,
+    #[serde(default)]
+    resumable: bool,
+    resume_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum ClientCommand {
+    Finish,
+}
+
+/// `GET /v1/stream`
+pub async fn stream(ws: WebSocketUpgrade, State(controller): State<Arc<VadServiceController>>) -> Response {
+    ws.max_message_size(crate::MAX_DECODING_MESSAGE_SIZE)
+        .on_upgrade(move |socket| handle_socket(socket, controller))
+}
+
+async fn handle_socket(mut socket: WebSocket, controller: Arc<VadServiceController>) {
+    if let Err(status) = run_session(&mut socket, &controller).await {
+        let message = ServerMessage::Error {
+            code: status.code() as i32,
+            message: status.message().to_string(),
+        };
+        if let Err(e) = send(&mut socket, &message).await {
+            log::debug!("Failed to send WebSocket error: {}", e.message());
+        }
+    }
+    // the client may have already gone away
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), Status> {
+    let text = serde_json::to_string(message).map_err(|e| Status::internal(e.to_string()))?;
+    socket
+        .send(Message::Text(text))
+        .await
+        .map_err(|e| Status::unavailable(e.to_string()))
+}
+
+/// Sends the events of every channel, tagging them with the channel index unless the audio is mono.
+async fn send_events(
+    socket: &mut WebSocket,
+    channel_events: Vec<Vec<VadEvent>>,
+    positions: IntegerPositions,
+) -> Result<(), Status> {
+    let multi_channel = channel_events.len() > 1;
+    for (channel, events) in channel_events.into_iter().enumerate() {
+        let channel = multi_channel.then_some(channel as u32);
+        for event in events {
+            send(socket, &ServerMessage::from_event(event, channel, positions)).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn receive_config(socket: &mut WebSocket) -> Result<SessionConfig, Status> {
+    match socket.recv().await {
+        Some(Ok(Message::Text(text))) => {
+            serde_json::from_str(&text).map_err(|e| Status::invalid_argument(format!("Invalid config: {}", e)))
+        }
+        Some(Ok(_)) => Err(Status::invalid_argument("First message must be config")),
+        Some(Err(e)) => Err(Status::unavailable(e.to_string())),
+        None => Err(Status::invalid_argument("No messages in stream")),
+    }
+}
+
+async fn run_session(socket: &mut WebSocket, controller: &VadServiceController) -> Result<(), Status> {
+    let SessionConfig {
+        config,
+        resumable,
+        resume_token,
+    } = receive_config(socket).await?;
+    if config.audio_type() == AudioType::OpusPackets {
+        return Err(Status::invalid_argument(
+            "OPUS_PACKETS audio is only supported by continuous DetectStream calls",
+        ));
+    }
+    let mut session = controller.start_session(config, resume_token.as_deref())?;
+
+    while let Some(message) = socket.recv().await {
+        match message.map_err(|e| Status::unavailable(e.to_string()))? {
+            Message::Binary(audio) => {
+                let events = session.push(&audio, &[])?;
+                send_events(socket, events, session.positions()).await?;
+                if resumable {
+                    let token = session.resume_token();
+                    send(socket, &ServerMessage::ResumeToken { token }).await?;
+                }
+            }
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(ClientCommand::Finish) => break,
+                Err(e) => return Err(Status::invalid_argument(format!("Invalid command: {}", e))),
+            },
+            Message::Close(_) => return Ok(()),
+            Message::Ping(_) | Message::Pong(_) => {}
+        }
+    }
+
+    let positions = session.positions();
+    let (events, _) = session.finish()?;
+    send_events(socket, events, positions).await
+}
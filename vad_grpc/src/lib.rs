@@ -14,6 +14,12 @@ pub enum VadServiceError {
         #[from]
         hound::Error,
     ),
+    #[error("Failed to decode audio: {0}")]
+    DecoderError(
+        #[source]
+        #[from]
+        symphonia::core::errors::Error,
+    ),
     #[error("Invalid audio: {0}")]
     InvalidAudio(String),
     #[error("Error: {0}")]
@@ -1,6 +1,10 @@
 use clap::{Arg, Command};
+use futures::TryFutureExt;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod controller;
+mod gateway;
 mod pb;
 mod service;
 mod settings;
@@ -9,8 +13,24 @@ mod tools;
 use crate::controller::VadServiceController;
 use crate::pb::vad_grpc_v1::{vad_recognizer_server, FILE_DESCRIPTOR_SET};
 use crate::settings::settings::Settings;
+pub(crate) use service::diarization::DiarizationService;
 pub(crate) use service::vad::VadService;
 
+pub(crate) const MAX_DECODING_MESSAGE_SIZE: usize = 100 * 1024 * 1024;
+const CASCADE_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Logs the frames seen and skipped by the energy gate of the cascade, whether the gateway serves `/v1/metrics` or
+/// not.
+async fn log_cascade_frames(controller: Arc<VadServiceController>) {
+    let mut interval = tokio::time::interval(CASCADE_LOG_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Some((frames, skipped_frames)) = controller.cascade_frames() {
+            log::info!("Cascade: {} of {} frames skipped by the energy gate", skipped_frames, frames);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let command = Command::new("vad_grpc_server")
@@ -37,13 +57,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // log::set_max_level(LevelFilter::from_str(settings.logging.log_level.as_str()).unwrap_or(LevelFilter::Info));
 
-    let vad_service = VadServiceController::new(&settings)?;
+    let vad_service = Arc::new(VadServiceController::new(&settings)?);
+    if settings.vad.cascade {
+        tokio::spawn(log_cascade_frames(vad_service.clone()));
+    }
 
     let addr = format!("{}:{}", settings.server.host, settings.server.port);
     println!("Server listening on {}", addr);
 
-    let vad_server =
-        vad_recognizer_server::VadRecognizerServer::new(vad_service).max_decoding_message_size(100 * 1024 * 1024);
+    let vad_server = vad_recognizer_server::VadRecognizerServer::from_arc(vad_service.clone())
+        .max_decoding_message_size(MAX_DECODING_MESSAGE_SIZE);
 
     let reflection_service_v1alpha = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
@@ -53,12 +76,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
         .build_v1()?;
 
-    tonic::transport::Server::builder()
+    let grpc_server = tonic::transport::Server::builder()
         .add_service(vad_server)
         .add_service(reflection_service_v1)
         .add_service(reflection_service_v1alpha)
         .serve(addr.parse()?)
-        .await?;
+        .err_into::<Box<dyn std::error::Error>>();
+
+    if settings.gateway.enabled {
+        let gateway_addr = format!("{}:{}", settings.gateway.host, settings.gateway.port);
+        println!("Gateway listening on {}", gateway_addr);
+        let gateway_server = gateway::serve(&gateway_addr, vad_service).err_into::<Box<dyn std::error::Error>>();
+        tokio::try_join!(grpc_server, gateway_server)?;
+    } else {
+        grpc_server.await?;
+    }
 
     Ok(())
 }
@@ -67,8 +99,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 mod tests {
     use crate::controller::VadServiceController;
     use crate::pb::vad_grpc_v1::vad_recognizer_client::VadRecognizerClient;
-    use crate::pb::vad_grpc_v1::{vad_recognizer_server, AudioType, VadRequest, VadStreamRequest, FILE_DESCRIPTOR_SET};
+    use crate::pb::vad_grpc_v1::{vad_recognizer_server, AudioType, VadRequest, VadStreamRequest};
     use crate::settings::settings::Settings;
+    use futures::TryStreamExt;
     use std::net::TcpListener;
     use std::sync::{Arc, LazyLock};
 
@@ -85,6 +118,8 @@ mod tests {
             sessions_num: 1,
             ..Default::default()
         },
+        gateway: Default::default(),
+        diarization: Default::default(),
     });
 
     fn get_free_port() -> u16 {
@@ -99,7 +134,7 @@ mod tests {
         let addr = format!("{}:{}", SETTINGS.server.host, port);
         println!("Server listening on {}", addr);
 
-        let server = tokio::spawn(async move {
+        tokio::spawn(async move {
             tonic::transport::Server::builder()
                 .add_service(
                     vad_recognizer_server::VadRecognizerServer::new(vad_service)
@@ -122,11 +157,14 @@ mod tests {
         let client = Arc::new(VadRecognizerClient::connect(addr).await.expect("Failed to connect"));
 
         let message = Arc::new(VadRequest {
-            audio: content.iter().map(|x| x.to_ne_bytes().to_vec()).flatten().collect(),
+            audio: content.iter().flat_map(|x| x.to_ne_bytes()).collect(),
             config: Some(crate::pb::vad_grpc_v1::AudioConfig {
                 audio_type: AudioType::RawPcmS16le as i32,
                 sample_rate: 16000,
+                channels: 1,
+                ..Default::default()
             }),
+            ..Default::default()
         });
 
         let handles: Vec<_> = (0..10)
@@ -162,24 +200,69 @@ mod tests {
         let content = vec![32532i16; 16000 * 10];
 
         let addr = format!("http://localhost:{}", port);
-        let client = Arc::new(VadRecognizerClient::connect(addr).await.expect("Failed to connect"));
-
-        let config_message = Arc::new(VadStreamRequest {
-            content: Some(crate::pb::vad_grpc_v1::vad_stream_request::Content::Config(
-                crate::pb::vad_grpc_v1::AudioConfig {
-                    audio_type: AudioType::RawPcmS16le as i32,
-                    sample_rate: 16000,
-                },
-            )),
-        });
+        let mut client = VadRecognizerClient::connect(addr).await.expect("Failed to connect");
+
+        let messages = |continuous: bool| {
+            let config_message = VadStreamRequest {
+                content: Some(crate::pb::vad_grpc_v1::vad_stream_request::Content::Config(
+                    crate::pb::vad_grpc_v1::AudioConfig {
+                        audio_type: AudioType::RawPcmS16le as i32,
+                        sample_rate: 16000,
+                        ..Default::default()
+                    },
+                )),
+                continuous,
+                ..Default::default()
+            };
+            let audio_messages = (1..=3).map(|request_id| VadStreamRequest {
+                content: Some(crate::pb::vad_grpc_v1::vad_stream_request::Content::Audio(
+                    crate::pb::vad_grpc_v1::vad_stream_request::Audio {
+                        audio: content.iter().flat_map(|x| x.to_ne_bytes()).collect(),
+                        request_id: request_id.to_string(),
+                        ..Default::default()
+                    },
+                )),
+                ..Default::default()
+            });
+            futures::stream::iter(
+                std::iter::once(config_message)
+                    .chain(audio_messages)
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        // every chunk is analysed on its own
+        let responses = client
+            .detect_stream(messages(false))
+            .await
+            .expect("Failed to call RPC")
+            .into_inner()
+            .try_collect::<Vec<_>>()
+            .await
+            .expect("Failed to receive responses");
+        let request_ids = responses
+            .iter()
+            .map(|response| response.request_id.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(request_ids, [Some("1"), Some("2"), Some("3")]);
+        for response in &responses {
+            assert_eq!(response.intervals.len(), 0);
+            assert_eq!(response.summary.map(|summary| summary.audio_duration_s), Some(10.0));
+        }
 
-        let message = Arc::new(VadStreamRequest {
-            content: Some(crate::pb::vad_grpc_v1::vad_stream_request::Content::Audio(
-                crate::pb::vad_grpc_v1::vad_stream_request::Audio {
-                    audio: content.iter().map(|x| x.to_ne_bytes().to_vec()).flatten().collect(),
-                    request_id: "1".to_string(),
-                },
-            )),
-        });
+        // a continuous stream ends with the summary of the whole audio
+        let responses = client
+            .detect_stream(messages(true))
+            .await
+            .expect("Failed to call RPC")
+            .into_inner()
+            .try_collect::<Vec<_>>()
+            .await
+            .expect("Failed to receive responses");
+        assert_eq!(responses.len(), 4);
+        let last = responses.last().unwrap();
+        assert_eq!(last.request_id, None);
+        assert_eq!(last.summary.map(|summary| summary.audio_duration_s), Some(30.0));
+        assert!(responses.iter().all(|response| response.intervals.is_empty()));
     }
 }
@@ -0,0 +1,36 @@
+use crate::settings::settings::{DiarizationSettings, EmbeddingInput};
+use silero_vad::diarization::{DiarizationParams, Diarizer, SpeakerHints, SpeakerTurn};
+
+pub struct DiarizationService {
+    diarizer: Diarizer,
+}
+
+impl DiarizationService {
+    pub fn new(settings: &DiarizationSettings) -> vad_grpc_server::Result<Self> {
+        let embedding_input = match settings.embedding_input {
+            EmbeddingInput::Fbank => silero_vad::diarization::EmbeddingInput::Fbank,
+            EmbeddingInput::Waveform => silero_vad::diarization::EmbeddingInput::Waveform,
+        };
+        let params = DiarizationParams {
+            embedding_input,
+            model_sample_rate: settings.model_sample_rate,
+            window_s: settings.window_s,
+            step_s: settings.step_s,
+            min_segment_s: settings.min_segment_s,
+            similarity_threshold: settings.similarity_threshold,
+            max_clustered_windows: settings.max_clustered_windows,
+        };
+        let diarizer = Diarizer::new(settings.model_path.as_str(), params, settings.sessions_num)?;
+        Ok(Self { diarizer })
+    }
+
+    pub fn diarize(
+        &self,
+        audio: &[i16],
+        sample_rate: u32,
+        speeches: &[silero_vad::TimeStamp],
+        hints: SpeakerHints,
+    ) -> vad_grpc_server::Result<Vec<SpeakerTurn>> {
+        Ok(self.diarizer.diarize(audio, sample_rate as usize, speeches, hints)?)
+    }
+}
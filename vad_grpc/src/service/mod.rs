@@ -1 +1,3 @@
+pub(crate) mod diarization;
+pub(crate) mod stream;
 pub(crate) mod vad;
@@ -0,0 +1,568 @@
+// the session fails with the `Status` returned by DetectStream and the WebSocket endpoint
+#![allow(clippy::result_large_err)]
+
+use crate::controller::VadServiceController;
+use crate::pb::vad_grpc_v1::{AudioConfig, AudioFormat, AudioType, OpusPacket, SpeechSummary};
+use crate::service::vad::VadService;
+use crate::tools;
+use crate::tools::grpc::{channel_timestamps_to_summary, IntegerPositions};
+use crate::tools::pcm::RawEncoding;
+use crate::tools::resample::StreamResampler;
+use base64::Engine;
+use silero_vad::{EngineKind, TimeStamp, VadEvent, VadStream};
+use std::borrow::Cow;
+use std::sync::Arc;
+use tonic::Status;
+use vad_grpc_server::VadServiceError;
+
+/// Streaming VAD of a continuous `DetectStream` call or a WebSocket session.
+///
+/// The format of the stream is read once, and every channel is resampled and analysed by its own `StreamResampler`
+/// and `VadStream`, so speech spanning several chunks is detected once, with positions counted from the beginning of
+/// the stream. The channels are set up on the first chunk, as the header of WAV streams defines their number.
+pub(crate) struct StreamSession {
+    vad: Arc<VadService>,
+    config: AudioConfig,
+    engine: EngineKind,
+    decoder: StreamDecoder,
+    resamplers: Vec<StreamResampler>,
+    streams: Vec<VadStream>,
+    /// Position of the streams when the session started, non-zero for resumed streams.
+    start_position: usize,
+    /// Finished speeches of every channel, for the summary of the session.
+    speeches: Vec<Vec<TimeStamp>>,
+    sample_rate: u32,
+    positions: IntegerPositions,
+    input_format: Option<AudioFormat>,
+}
+
+impl StreamSession {
+    /// Starts a session, resumed from the `resume_token` of a previous session if set.
+    pub fn new(vad: Arc<VadService>, config: AudioConfig, resume_token: Option<&str>) -> Result<Self, Status> {
+        let mut session = Self {
+            vad,
+            engine: VadServiceController::engine(&config),
+            decoder: StreamDecoder::new(&config)?,
+            resamplers: Vec::new(),
+            config,
+            streams: Vec::new(),
+            start_position: 0,
+            speeches: Vec::new(),
+            sample_rate: 0,
+            positions: IntegerPositions::default(),
+            input_format: None,
+        };
+        if let Some(token) = resume_token {
+            session.resume(token)?;
+        }
+        Ok(session)
+    }
+
+    /// Positions of the integer timestamps requested in the config, for the audio pushed so far.
+    pub fn positions(&self) -> IntegerPositions {
+        self.positions
+    }
+
+    /// Format of the audio pushed so far, `None` before the first chunk.
+    pub fn input_format(&self) -> Option<AudioFormat> {
+        self.input_format
+    }
+
+    /// Pushes the next chunk of audio, or the packets of `OPUS_PACKETS` streams, and returns the events of every
+    /// channel.
+    pub fn push(&mut self, audio: &[u8], opus_packets: &[OpusPacket]) -> Result<Vec<Vec<VadEvent>>, Status> {
+        let invalid_audio = |e: VadServiceError| Status::invalid_argument(e.to_string());
+        let (samples, input_format) = self
+            .decoder
+            .decode(audio, opus_packets, &self.config)
+            .map_err(invalid_audio)?;
+        let channels =
+            VadServiceController::split_channels(samples, &input_format, &self.config).map_err(invalid_audio)?;
+        match self.input_format {
+            None => self.start(&input_format, channels.len())?,
+            Some(format) if format.sample_rate != input_format.sample_rate => {
+                return Err(Status::invalid_argument(format!(
+                    "Sample rate changed from {} to {}",
+                    format.sample_rate, input_format.sample_rate
+                )))
+            }
+            Some(_) if self.streams.len() != channels.len() => {
+                return Err(Status::invalid_argument(format!(
+                    "Expected {} channels, got {}",
+                    self.streams.len(),
+                    channels.len()
+                )))
+            }
+            Some(_) => {}
+        }
+        self.input_format = Some(input_format);
+
+        let channels = self
+            .resamplers
+            .iter_mut()
+            .zip(&channels)
+            .map(|(resampler, samples)| resampler.push(samples))
+            .collect::<vad_grpc_server::Result<Vec<_>>>()
+            .map_err(invalid_audio)?;
+        self.push_samples(&channels)
+    }
+
+    fn push_samples(&mut self, channels: &[Vec<i16>]) -> Result<Vec<Vec<VadEvent>>, Status> {
+        let events = self
+            .streams
+            .iter_mut()
+            .zip(channels)
+            .map(|(stream, samples)| stream.push(samples))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Status::internal(e.to_string()))?;
+        self.record(&events);
+        Ok(events)
+    }
+
+    /// Sets up the resamplers and streams of the channels on the first chunk.
+    fn start(&mut self, input_format: &AudioFormat, channels: usize) -> Result<(), Status> {
+        let sample_rate = tools::resample::model_sample_rate(input_format.sample_rate);
+        self.resamplers = (0..channels)
+            .map(|_| StreamResampler::new(input_format.sample_rate, sample_rate))
+            .collect::<vad_grpc_server::Result<_>>()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        self.streams = (0..channels)
+            .map(|_| {
+                self.vad
+                    .stream(sample_rate, self.engine)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+        self.set_format(*input_format);
+        Ok(())
+    }
+
+    fn set_format(&mut self, input_format: AudioFormat) {
+        self.sample_rate = tools::resample::model_sample_rate(input_format.sample_rate);
+        self.positions = IntegerPositions::with_rates(&self.config, self.sample_rate, input_format.sample_rate);
+        self.start_position = self.streams.first().map_or(0, VadStream::position);
+        self.speeches = vec![Vec::new(); self.streams.len()];
+        self.input_format = Some(input_format);
+    }
+
+    fn record(&mut self, events: &[Vec<VadEvent>]) {
+        for (speeches, events) in self.speeches.iter_mut().zip(events) {
+            speeches.extend(events.iter().filter_map(|event| match event {
+                VadEvent::SpeechEnd(timestamp) => Some(timestamp.clone()),
+                VadEvent::SpeechStart { .. } => None,
+            }));
+        }
+    }
+
+    /// Encodes the state of the session: the format of the stream and the bytes of its incomplete frame, then the
+    /// states of the resampler and the VAD stream of every channel, each prefixed by its length. The state of an Opus
+    /// decoder can't be saved, so `OPUS_PACKETS` streams are not resumable.
+    pub fn resume_token(&self) -> String {
+        let format = self.input_format.map_or_else(Vec::new, |format| {
+            [format.audio_type as u32, format.sample_rate, format.channels]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        });
+        let mut sections = vec![format, self.decoder.pending_bytes().to_vec()];
+        for (resampler, stream) in self.resamplers.iter().zip(&self.streams) {
+            sections.push(resampler.save_state());
+            sections.push(stream.save_state());
+        }
+
+        let mut token = Vec::new();
+        for section in sections {
+            token.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            token.extend_from_slice(&section);
+        }
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token)
+    }
+
+    /// Restores the state saved by `resume_token`. A token saved before the first chunk leaves the session as new.
+    fn resume(&mut self, token: &str) -> Result<(), Status> {
+        let invalid = |e: &dyn std::fmt::Display| Status::invalid_argument(format!("Invalid resume token: {}", e));
+        let sections = decode_resume_token(token)?;
+        let [format, pending, channels @ ..] = sections.as_slice() else {
+            return Err(invalid(&"missing format"));
+        };
+        let format = match format.as_slice() {
+            [] => return Ok(()),
+            bytes if bytes.len() == 12 => {
+                let value = |i: usize| u32::from_le_bytes(bytes[i * 4..(i + 1) * 4].try_into().expect("4 bytes"));
+                AudioFormat {
+                    audio_type: value(0) as i32,
+                    sample_rate: value(1),
+                    channels: value(2),
+                }
+            }
+            _ => return Err(invalid(&"invalid format")),
+        };
+        if channels.is_empty() || !channels.len().is_multiple_of(2) {
+            return Err(invalid(&"invalid channels"));
+        }
+
+        let sample_rate = tools::resample::model_sample_rate(format.sample_rate);
+        for state in channels.chunks_exact(2) {
+            let resampler =
+                StreamResampler::resume(format.sample_rate, sample_rate, &state[0]).map_err(|e| invalid(&e))?;
+            let stream = self
+                .vad
+                .resume_stream(sample_rate, self.engine, &state[1])
+                .map_err(|e| invalid(&e))?;
+            self.resamplers.push(resampler);
+            self.streams.push(stream);
+        }
+        self.decoder.resume(format, pending.clone());
+        self.set_format(format);
+        Ok(())
+    }
+
+    /// Flushes the audio delayed by the resamplers and the speech in progress and returns their events, with the
+    /// summary of the audio of the session.
+    pub fn finish(mut self) -> Result<(Vec<Vec<VadEvent>>, SpeechSummary), Status> {
+        let tails = std::mem::take(&mut self.resamplers)
+            .into_iter()
+            .map(StreamResampler::finish)
+            .collect::<vad_grpc_server::Result<Vec<_>>>()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let mut events = self.push_samples(&tails)?;
+        let position = self.streams.first().map_or(0, VadStream::position);
+        let last_events = std::mem::take(&mut self.streams)
+            .into_iter()
+            .map(VadStream::finish)
+            .collect::<Vec<_>>();
+        self.record(&last_events);
+        for (events, last_events) in events.iter_mut().zip(last_events) {
+            events.extend(last_events);
+        }
+
+        // a resumed session only knows the speeches since it started, so the summary covers its own audio
+        let offset = self.start_position as f64 / self.sample_rate.max(1) as f64;
+        let speeches = self
+            .speeches
+            .iter()
+            .map(|speeches| {
+                speeches
+                    .iter()
+                    .map(|speech| TimeStamp {
+                        start: speech.start - offset,
+                        end: speech.end - offset,
+                        ..speech.clone()
+                    })
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        let summary = channel_timestamps_to_summary(&speeches, position - self.start_position, self.sample_rate);
+        Ok((events, summary))
+    }
+}
+
+/// Decoding state of the audio of a stream.
+enum StreamDecoder {
+    /// WAV or AUTO stream before its first chunk, whose header defines the format of the stream.
+    Header(AudioType),
+    /// Raw audio, and WAV audio after the header of the first chunk.
+    Raw {
+        encoding: RawEncoding,
+        format: AudioFormat,
+        /// Bytes of the incomplete frame of interleaved samples at the end of the last chunk.
+        pending: Vec<u8>,
+    },
+    /// Every chunk is a complete FLAC, Ogg or MP3 file.
+    Container(AudioType),
+    #[cfg(feature = "opus")]
+    OpusPackets(tools::opus::OpusPacketDecoder),
+}
+
+impl StreamDecoder {
+    fn new(config: &AudioConfig) -> Result<Self, Status> {
+        let audio_type = config.audio_type();
+        if let Some(encoding) = RawEncoding::from_audio_type(audio_type) {
+            let format = AudioFormat {
+                audio_type: audio_type as i32,
+                sample_rate: config.sample_rate.max(0) as u32,
+                channels: config.channels.max(1),
+            };
+            return Ok(StreamDecoder::Raw {
+                encoding,
+                format,
+                pending: Vec::new(),
+            });
+        }
+        match audio_type {
+            #[cfg(feature = "opus")]
+            AudioType::OpusPackets => tools::opus::OpusPacketDecoder::new(config.channels.max(1) as usize)
+                .map(StreamDecoder::OpusPackets)
+                .map_err(|e| Status::invalid_argument(e.to_string())),
+            #[cfg(not(feature = "opus"))]
+            AudioType::OpusPackets | AudioType::OggOpus => Err(VadServiceController::opus_unimplemented()),
+            AudioType::Flac | AudioType::OggVorbis | AudioType::Mp3 => Ok(StreamDecoder::Container(audio_type)),
+            #[cfg(feature = "opus")]
+            AudioType::OggOpus => Ok(StreamDecoder::Container(audio_type)),
+            audio_type => Ok(StreamDecoder::Header(audio_type)),
+        }
+    }
+
+    /// Decodes a chunk into interleaved samples, returned with the format of the stream.
+    fn decode<'a>(
+        &mut self,
+        mut audio: &'a [u8],
+        #[cfg_attr(not(feature = "opus"), allow(unused_variables))] opus_packets: &[OpusPacket],
+        config: &AudioConfig,
+    ) -> vad_grpc_server::Result<(Cow<'a, [i16]>, AudioFormat)> {
+        if let StreamDecoder::Header(audio_type) = *self {
+            let data_offset;
+            (*self, data_offset) = Self::read_header(audio, audio_type, config)?;
+            audio = &audio[data_offset..];
+        }
+        match self {
+            StreamDecoder::Header(_) => unreachable!("the header is read above"),
+            StreamDecoder::Raw {
+                encoding,
+                format,
+                pending,
+            } => {
+                // a chunk may end in the middle of a frame, whose bytes wait for the next chunk
+                let frame_len = encoding.bytes_per_sample() * format.channels.max(1) as usize;
+                if pending.is_empty() && audio.len().is_multiple_of(frame_len) {
+                    return Ok((tools::pcm::decode(audio, *encoding)?, *format));
+                }
+                let mut bytes = std::mem::take(pending);
+                bytes.extend_from_slice(audio);
+                *pending = bytes.split_off(bytes.len() - bytes.len() % frame_len);
+                let samples = tools::pcm::decode(&bytes, *encoding)?.into_owned();
+                Ok((Cow::Owned(samples), *format))
+            }
+            StreamDecoder::Container(audio_type) => {
+                let (samples, channels, sample_rate) = match audio_type {
+                    #[cfg(feature = "opus")]
+                    AudioType::OggOpus => tools::opus::decode_ogg_opus(audio)?,
+                    #[cfg(not(feature = "opus"))]
+                    AudioType::OggOpus => {
+                        return Err(VadServiceError::InvalidAudio(
+                            "The server is built without Opus support".to_string(),
+                        ))
+                    }
+                    _ => tools::compressed::decode(audio, *audio_type)?,
+                };
+                VadServiceController::check_format(channels, sample_rate, config)?;
+                let format = AudioFormat {
+                    audio_type: *audio_type as i32,
+                    sample_rate,
+                    channels: channels as u32,
+                };
+                Ok((Cow::Owned(samples), format))
+            }
+            #[cfg(feature = "opus")]
+            StreamDecoder::OpusPackets(decoder) => {
+                let format = AudioFormat {
+                    audio_type: AudioType::OpusPackets as i32,
+                    sample_rate: tools::opus::OPUS_SAMPLE_RATE,
+                    channels: config.channels.max(1),
+                };
+                Ok((Cow::Owned(decoder.decode(opus_packets)?), format))
+            }
+        }
+    }
+
+    fn pending_bytes(&self) -> &[u8] {
+        match self {
+            StreamDecoder::Raw { pending, .. } => pending,
+            _ => &[],
+        }
+    }
+
+    /// Continues a raw or WAV stream of `format` from the bytes of its incomplete frame. The header of a resumed WAV
+    /// stream was in the chunks of the previous session.
+    fn resume(&mut self, format: AudioFormat, bytes: Vec<u8>) {
+        match self {
+            StreamDecoder::Raw { pending, .. } => *pending = bytes,
+            StreamDecoder::Header(_) => {
+                if let Some(encoding) = RawEncoding::from_wav_audio_type(format.audio_type()) {
+                    *self = StreamDecoder::Raw {
+                        encoding,
+                        format,
+                        pending: bytes,
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads the format of a WAV or AUTO stream from its first chunk. WAV streams continue as raw PCM, returned with
+    /// the offset of the samples in the chunk; every chunk of other containers is a complete file.
+    fn read_header(
+        audio: &[u8],
+        audio_type: AudioType,
+        config: &AudioConfig,
+    ) -> vad_grpc_server::Result<(Self, usize)> {
+        let audio_type = match audio_type {
+            AudioType::Auto => tools::sniff::detect_audio_type(audio).ok_or_else(|| {
+                VadServiceError::InvalidAudio(
+                    "Unable to detect the audio format, raw audio requires audio_type".to_string(),
+                )
+            })?,
+            audio_type => audio_type,
+        };
+        let Some(encoding) = RawEncoding::from_wav_audio_type(audio_type) else {
+            return Ok((StreamDecoder::Container(audio_type), 0));
+        };
+        tools::wav::check_wav_audio_type(audio, audio_type)?;
+        let (format, data_offset) = tools::wav::read_stream_header(audio)?;
+        VadServiceController::check_format(format.channels as usize, format.sample_rate, config)?;
+        let decoder = StreamDecoder::Raw {
+            encoding,
+            format,
+            pending: Vec::new(),
+        };
+        Ok((decoder, data_offset))
+    }
+}
+
+/// Splits a resume token into the stream states of the channels.
+fn decode_resume_token(token: &str) -> Result<Vec<Vec<u8>>, Status> {
+    let invalid = || Status::invalid_argument("Invalid resume token");
+    let token = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(token)
+        .map_err(|_| invalid())?;
+    let mut rest = token.as_slice();
+    let mut states = Vec::new();
+    while let Some((len, tail)) = rest.split_first_chunk::<4>() {
+        let len = u32::from_le_bytes(*len) as usize;
+        if tail.len() < len {
+            return Err(invalid());
+        }
+        let (state, tail) = tail.split_at(len);
+        states.push(state.to_vec());
+        rest = tail;
+    }
+    if states.is_empty() || !rest.is_empty() {
+        return Err(invalid());
+    }
+    Ok(states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::transcode::SampleFormat;
+
+    fn decode_chunks(decoder: &mut StreamDecoder, chunks: &[&[u8]], config: &AudioConfig) -> (Vec<i16>, AudioFormat) {
+        let mut samples = Vec::new();
+        let mut format = AudioFormat::default();
+        for chunk in chunks {
+            let decoded;
+            (decoded, format) = decoder.decode(chunk, &[], config).unwrap();
+            samples.extend_from_slice(&decoded);
+        }
+        (samples, format)
+    }
+
+    #[test]
+    fn test_wav_stream_continues_after_header() {
+        let samples = (0..1000).map(|i| (i * 37 % 2000 - 1000) as i16).collect::<Vec<_>>();
+        let wav = tools::wav::samples_to_wav(&samples, 16000, SampleFormat::S16).unwrap();
+        // the header and the first samples, then samples without a header
+        let (first, rest) = wav.split_at(44 + 200);
+        let (second, third) = rest.split_at(1000);
+
+        for audio_type in [AudioType::WavPcmS16le, AudioType::Auto] {
+            let config = AudioConfig {
+                audio_type: audio_type as i32,
+                ..Default::default()
+            };
+            let mut decoder = StreamDecoder::new(&config).unwrap();
+            let (decoded, format) = decode_chunks(&mut decoder, &[first, second, third], &config);
+            assert_eq!(decoded, samples);
+            assert_eq!(format.audio_type(), AudioType::WavPcmS16le);
+            assert_eq!((format.sample_rate, format.channels), (16000, 1));
+        }
+    }
+
+    #[test]
+    fn test_raw_stream_frames_span_chunks() {
+        // stereo 16-bit audio cut in the middle of samples and frames
+        let samples = (0..600).map(|i| i as i16 * 50).collect::<Vec<_>>();
+        let bytes = samples.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+        let config = AudioConfig {
+            audio_type: AudioType::RawPcmS16le as i32,
+            sample_rate: 16000,
+            channels: 2,
+            ..Default::default()
+        };
+        let mut decoder = StreamDecoder::new(&config).unwrap();
+        let (first, rest) = bytes.split_at(401);
+        let (second, third) = rest.split_at(6);
+        let (decoded, _) = decode_chunks(&mut decoder, &[first, second, third], &config);
+        assert_eq!(decoded, samples);
+
+        let (partial, _) = decoder.decode(&bytes[..3], &[], &config).unwrap();
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn test_resumed_wav_stream_continues_without_header() {
+        let samples = (0..1000).map(|i| (i * 37 % 2000 - 1000) as i16).collect::<Vec<_>>();
+        let wav = tools::wav::samples_to_wav(&samples, 8000, SampleFormat::S16).unwrap();
+        // the first chunk ends in the middle of a sample
+        let (first, rest) = wav.split_at(44 + 201);
+        let config = AudioConfig {
+            audio_type: AudioType::WavPcmS16le as i32,
+            ..Default::default()
+        };
+        let mut decoder = StreamDecoder::new(&config).unwrap();
+        let (mut decoded, format) = decode_chunks(&mut decoder, &[first], &config);
+
+        let mut resumed = StreamDecoder::new(&config).unwrap();
+        resumed.resume(format, decoder.pending_bytes().to_vec());
+        decoded.extend(decode_chunks(&mut resumed, &[rest], &config).0);
+        assert_eq!(decoded, samples);
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn test_opus_packets_continue_across_chunks() {
+        use audiopus::coder::Encoder;
+        use audiopus::{Application, Channels, SampleRate};
+
+        let encoder = Encoder::new(SampleRate::Hz16000, Channels::Mono, Application::Voip).unwrap();
+        let frame = (0..320)
+            .map(|i| ((i as f32 * 0.1).sin() * 8000.0) as i16)
+            .collect::<Vec<_>>();
+        let packets = (0..10)
+            .map(|sequence_number| {
+                let mut data = vec![0; 4000];
+                let len = encoder.encode(&frame, &mut data).unwrap();
+                data.truncate(len);
+                OpusPacket { data, sequence_number }
+            })
+            .collect::<Vec<_>>();
+        let config = AudioConfig {
+            audio_type: AudioType::OpusPackets as i32,
+            ..Default::default()
+        };
+        let mut decoder = StreamDecoder::new(&config).unwrap();
+
+        // the packets 4 and 5 are lost between the chunks and concealed at the start of the second one
+        let mut samples = 0;
+        for chunk in [&packets[..4], &packets[6..]] {
+            let (decoded, format) = decoder.decode(&[], chunk, &config).unwrap();
+            assert_eq!(format.sample_rate, tools::opus::OPUS_SAMPLE_RATE);
+            samples += decoded.len();
+        }
+        assert_eq!(samples, 10 * 320);
+    }
+
+    #[test]
+    fn test_wav_stream_header_must_match_config() {
+        let wav = tools::wav::samples_to_wav(&[0; 100], 8000, SampleFormat::S16).unwrap();
+        let config = AudioConfig {
+            audio_type: AudioType::WavPcmS16le as i32,
+            sample_rate: 16000,
+            ..Default::default()
+        };
+        let mut decoder = StreamDecoder::new(&config).unwrap();
+        assert!(decoder.decode(&wav, &[], &config).is_err());
+    }
+}
@@ -1,10 +1,29 @@
-use crate::settings::settings::VadSettings;
+use crate::settings::settings::{SmoothingKind, VadSettings};
+use silero_vad::engine::{EnergyVad, WebRtcMode, WebRtcVad};
+use silero_vad::EngineKind;
 
-pub struct VadService {
+/// Recognizers of one engine at the sample rates supported by the VAD.
+struct EngineRecognizers {
     recognizer_8k: silero_vad::Recognizer,
     recognizer_16k: silero_vad::Recognizer,
 }
 
+impl EngineRecognizers {
+    fn get(&self, sample_rate: u32) -> vad_grpc_server::Result<&silero_vad::Recognizer> {
+        match sample_rate {
+            8000 => Ok(&self.recognizer_8k),
+            16000 => Ok(&self.recognizer_16k),
+            _ => Err(vad_grpc_server::VadServiceError::InvalidAudio("Unsupported sample rate".to_string())),
+        }
+    }
+}
+
+pub struct VadService {
+    silero: EngineRecognizers,
+    webrtc: EngineRecognizers,
+    energy: EngineRecognizers,
+}
+
 impl VadService {
     pub fn new(settings: &VadSettings) -> vad_grpc_server::Result<Self> {
         fn vad_params(settings: &VadSettings, sample_rate: usize) -> silero_vad::VadParams {
@@ -12,32 +31,100 @@ impl VadService {
                 sample_rate,
                 frame_size: settings.frame_size,
                 threshold: settings.threshold,
+                neg_threshold: settings.neg_threshold,
+                adaptive_window_s: settings.adaptive_window_s,
+                smoothing: settings.smoothing.map(|smoothing| match smoothing.kind {
+                    SmoothingKind::MovingAverage => silero_vad::Smoothing::MovingAverage(smoothing.frames),
+                    SmoothingKind::Median => silero_vad::Smoothing::Median(smoothing.frames),
+                }),
+                onset_frames: settings.onset_frames,
+                offset_frames: settings.offset_frames,
                 min_silence_duration_ms: settings.min_silence_duration_ms,
                 speech_pad_ms: settings.speech_pad_ms,
                 min_speech_duration_ms: settings.min_speech_duration_ms,
                 max_speech_duration_s: settings.max_speech_duration_s,
-                ..Default::default()
+                min_silence_at_max_speech_ms: settings.min_silence_at_max_speech_ms,
             }
         }
 
         let vad_params_8k = vad_params(settings, 8000);
         let vad_params_16k = vad_params(settings, 16000);
 
-        let recognizer_8k =
-            silero_vad::Recognizer::new(settings.model_path.as_str(), vad_params_8k, settings.sessions_num)?;
-        let recognizer_16k =
-            silero_vad::Recognizer::new(settings.model_path.as_str(), vad_params_16k, settings.sessions_num)?;
+        let silero = |params: &silero_vad::VadParams| {
+            if settings.cascade {
+                silero_vad::Recognizer::cascade(settings.model_path.as_str(), params.clone(), settings.sessions_num)
+            } else {
+                silero_vad::Recognizer::new(settings.model_path.as_str(), params.clone(), settings.sessions_num)
+            }
+        };
+        let recognizer_8k = silero(&vad_params_8k)?;
+        let recognizer_16k = silero(&vad_params_16k)?;
+
+        let webrtc_mode = WebRtcMode::try_from(settings.webrtc_mode)?;
+        let webrtc = |params: &silero_vad::VadParams| {
+            let sample_rate = params.sample_rate;
+            silero_vad::Recognizer::with_engine(params.clone(), move || {
+                Box::new(WebRtcVad::new(sample_rate, webrtc_mode))
+            })
+        };
+        let energy = |params: &silero_vad::VadParams| {
+            let sample_rate = params.sample_rate;
+            silero_vad::Recognizer::with_engine(params.clone(), move || Box::new(EnergyVad::new(sample_rate)))
+        };
+
         Ok(Self {
-            recognizer_8k,
-            recognizer_16k,
+            silero: EngineRecognizers {
+                recognizer_8k,
+                recognizer_16k,
+            },
+            webrtc: EngineRecognizers {
+                recognizer_8k: webrtc(&vad_params_8k),
+                recognizer_16k: webrtc(&vad_params_16k),
+            },
+            energy: EngineRecognizers {
+                recognizer_8k: energy(&vad_params_8k),
+                recognizer_16k: energy(&vad_params_16k),
+            },
         })
     }
 
-    pub fn recognize(&self, audio: Vec<i16>, sample_rate: u32) -> vad_grpc_server::Result<Vec<silero_vad::TimeStamp>> {
-        match sample_rate {
-            8000 => Ok(self.recognizer_8k.process(&*audio)?),
-            16000 => Ok(self.recognizer_16k.process(&*audio)?),
-            _ => Err(vad_grpc_server::VadServiceError::InvalidAudio("Unsupported sample rate".to_string())),
+    fn recognizers(&self, engine: EngineKind) -> &EngineRecognizers {
+        match engine {
+            EngineKind::Silero => &self.silero,
+            EngineKind::WebRtc => &self.webrtc,
+            EngineKind::Energy => &self.energy,
         }
     }
+
+    pub fn recognize(
+        &self,
+        audio: &[i16],
+        sample_rate: u32,
+        engine: EngineKind,
+    ) -> vad_grpc_server::Result<Vec<silero_vad::TimeStamp>> {
+        Ok(self.recognizers(engine).get(sample_rate)?.process(audio)?)
+    }
+
+    /// Frames seen and skipped by the energy gate of Silero at all sample rates, `None` if the cascade is disabled.
+    pub fn cascade_frames(&self) -> Option<(u64, u64)> {
+        let stats_8k = self.silero.recognizer_8k.cascade_stats()?;
+        let stats_16k = self.silero.recognizer_16k.cascade_stats()?;
+        Some((
+            stats_8k.frames() + stats_16k.frames(),
+            stats_8k.skipped_frames() + stats_16k.skipped_frames(),
+        ))
+    }
+
+    pub fn stream(&self, sample_rate: u32, engine: EngineKind) -> vad_grpc_server::Result<silero_vad::VadStream> {
+        Ok(self.recognizers(engine).get(sample_rate)?.stream())
+    }
+
+    pub fn resume_stream(
+        &self,
+        sample_rate: u32,
+        engine: EngineKind,
+        state: &[u8],
+    ) -> vad_grpc_server::Result<silero_vad::VadStream> {
+        Ok(self.recognizers(engine).get(sample_rate)?.resume_stream(state)?)
+    }
 }
@@ -1,6 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod settings {
     use config::builder::DefaultState;
-    use config::{Config, ConfigBuilder, Environment, File};
+    use config::{ConfigBuilder, Environment, File};
     use log::{log, Level};
     use serde::{Deserialize, Serialize};
     use serde_json::to_string_pretty;
@@ -17,6 +18,37 @@ pub mod settings {
         pub log_level: String,
     }
 
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(default)]
+    pub struct Gateway {
+        pub enabled: bool,
+        pub host: String,
+        pub port: i32,
+    }
+
+    impl Default for Gateway {
+        fn default() -> Self {
+            Self {
+                enabled: false,
+                host: "0.0.0.0".to_string(),
+                port: 8080,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum SmoothingKind {
+        MovingAverage,
+        Median,
+    }
+
+    #[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+    pub struct SmoothingSettings {
+        pub kind: SmoothingKind,
+        pub frames: usize,
+    }
+
     #[derive(Debug, Deserialize, Serialize)]
     #[serde(default)]
     pub struct VadSettings {
@@ -24,10 +56,26 @@ pub mod settings {
         pub sessions_num: u8,
         pub frame_size: usize,
         pub threshold: f32,
+        /// Probability below which a speech ends, `threshold - 0.15` when absent.
+        pub neg_threshold: Option<f32>,
+        /// Duration of the rolling window the thresholds adapt over, fixed thresholds when absent.
+        pub adaptive_window_s: Option<f32>,
+        /// Smoothing of the speech probabilities, none when absent.
+        pub smoothing: Option<SmoothingSettings>,
+        /// Consecutive speech frames starting a speech and silence frames ending it.
+        pub onset_frames: usize,
+        pub offset_frames: usize,
         pub min_silence_duration_ms: usize,
         pub speech_pad_ms: usize,
         pub min_speech_duration_ms: usize,
         pub max_speech_duration_s: f32,
+        /// Shortest pause a speech longer than `max_speech_duration_s` can be split at.
+        pub min_silence_at_max_speech_ms: usize,
+        /// Aggressiveness of the WebRTC engine, from 0 (quality) to 3 (very aggressive).
+        pub webrtc_mode: u8,
+        /// Skips Silero on the frames an energy gate takes for silence, for mostly silent audio. Replaces the Silero
+        /// engine of every request; the skipped frames are logged every minute.
+        pub cascade: bool,
     }
 
     impl Default for VadSettings {
@@ -37,10 +85,58 @@ pub mod settings {
                 sessions_num: 1,
                 frame_size: 64,
                 threshold: 0.5,
+                neg_threshold: None,
+                adaptive_window_s: None,
+                smoothing: None,
+                onset_frames: 1,
+                offset_frames: 1,
                 min_silence_duration_ms: 0,
                 speech_pad_ms: 64,
                 min_speech_duration_ms: 64,
                 max_speech_duration_s: f32::INFINITY,
+                min_silence_at_max_speech_ms: 98,
+                webrtc_mode: 2,
+                cascade: false,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum EmbeddingInput {
+        Fbank,
+        Waveform,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(default)]
+    pub struct DiarizationSettings {
+        pub enabled: bool,
+        pub model_path: String,
+        pub sessions_num: u8,
+        pub embedding_input: EmbeddingInput,
+        pub model_sample_rate: usize,
+        pub window_s: f32,
+        pub step_s: f32,
+        pub min_segment_s: f32,
+        pub similarity_threshold: f32,
+        /// Most speaker windows clustered at once, longer audio is clustered in two stages.
+        pub max_clustered_windows: usize,
+    }
+
+    impl Default for DiarizationSettings {
+        fn default() -> Self {
+            Self {
+                enabled: false,
+                model_path: "model/speaker_embedding.onnx".to_string(),
+                sessions_num: 1,
+                embedding_input: EmbeddingInput::Fbank,
+                model_sample_rate: 16000,
+                window_s: 1.5,
+                step_s: 0.75,
+                min_segment_s: 0.3,
+                similarity_threshold: 0.5,
+                max_clustered_windows: 1000,
             }
         }
     }
@@ -50,6 +146,10 @@ pub mod settings {
         pub server: Server,
         pub logging: Logging,
         pub vad: VadSettings,
+        #[serde(default)]
+        pub gateway: Gateway,
+        #[serde(default)]
+        pub diarization: DiarizationSettings,
     }
 
     impl Settings {
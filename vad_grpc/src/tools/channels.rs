@@ -0,0 +1,129 @@
+use crate::pb::vad_grpc_v1::{AudioFormat, ChannelMode};
+use std::borrow::Cow;
+use vad_grpc_server::VadServiceError;
+
+/// Decoded audio, one vector of samples per channel. Mono 16-bit audio at the model rate borrows the request.
+pub struct DecodedAudio<'a> {
+    pub channels: Vec<Cow<'a, [i16]>>,
+    pub sample_rate: u32,
+    /// The channels before resampling, `None` if the input is at the model rate.
+    pub input_rate_channels: Option<Vec<Cow<'a, [i16]>>>,
+    /// Format of the input audio before channel mixing and resampling.
+    pub input_format: AudioFormat,
+}
+
+impl<'a> DecodedAudio<'a> {
+    /// The channels at the sample rate of the input, which the audio returned to the client is cut from.
+    pub fn input_channels(&self) -> &[Cow<'a, [i16]>] {
+        self.input_rate_channels.as_deref().unwrap_or(&self.channels)
+    }
+}
+
+/// Length of the frames in which `ChannelMode::DownmixMaxEnergy` picks the loudest channel.
+const MAX_ENERGY_FRAME_MS: usize = 20;
+
+/// Splits interleaved samples into one vector per channel. Mono samples are passed through, borrowed if they are.
+pub fn deinterleave(samples: Cow<'_, [i16]>, channels: usize) -> vad_grpc_server::Result<Vec<Cow<'_, [i16]>>> {
+    if channels == 0 || !samples.len().is_multiple_of(channels) {
+        return Err(VadServiceError::InvalidAudio(format!(
+            "Audio of {} samples can't be split into {} channels",
+            samples.len(),
+            channels
+        )));
+    }
+    if channels == 1 {
+        return Ok(vec![samples]);
+    }
+    Ok((0..channels)
+        .map(|channel| Cow::Owned(samples.iter().skip(channel).step_by(channels).copied().collect()))
+        .collect())
+}
+
+/// Turns the decoded channels into the channels passed to the VAD according to `mode`.
+pub fn apply_channel_mode(
+    channels: Vec<Cow<'_, [i16]>>,
+    mode: ChannelMode,
+    selected_channel: usize,
+    sample_rate: usize,
+) -> vad_grpc_server::Result<Vec<Cow<'_, [i16]>>> {
+    match mode {
+        ChannelMode::Separate => Ok(channels),
+        // there is nothing to mix in mono audio
+        ChannelMode::DownmixAverage | ChannelMode::DownmixMaxEnergy if channels.len() == 1 => Ok(channels),
+        ChannelMode::Select => {
+            let num_channels = channels.len();
+            let channel = channels.into_iter().nth(selected_channel).ok_or_else(|| {
+                VadServiceError::InvalidAudio(format!(
+                    "Channel {} selected, but the audio has {} channels",
+                    selected_channel, num_channels
+                ))
+            })?;
+            Ok(vec![channel])
+        }
+        ChannelMode::DownmixAverage => Ok(vec![Cow::Owned(downmix_average(&channels))]),
+        ChannelMode::DownmixMaxEnergy => {
+            let frame_len = (sample_rate * MAX_ENERGY_FRAME_MS / 1000).max(1);
+            Ok(vec![Cow::Owned(downmix_max_energy(&channels, frame_len))])
+        }
+    }
+}
+
+fn downmix_average(channels: &[Cow<'_, [i16]>]) -> Vec<i16> {
+    let len = channels.first().map_or(0, |channel| channel.len());
+    (0..len)
+        .map(|i| {
+            let sum = channels.iter().map(|channel| channel[i] as i32).sum::<i32>();
+            (sum / channels.len() as i32) as i16
+        })
+        .collect()
+}
+
+/// Takes every frame of `frame_len` samples from the channel with the highest energy in that frame.
+fn downmix_max_energy(channels: &[Cow<'_, [i16]>], frame_len: usize) -> Vec<i16> {
+    let len = channels.first().map_or(0, |channel| channel.len());
+    let mut mix = Vec::with_capacity(len);
+    for start in (0..len).step_by(frame_len) {
+        let frame = start..(start + frame_len).min(len);
+        let loudest = channels
+            .iter()
+            .max_by_key(|channel| channel[frame.clone()].iter().map(|&x| (x as i64).pow(2)).sum::<i64>())
+            .expect("at least one channel");
+        mix.extend_from_slice(&loudest[frame]);
+    }
+    mix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deinterleave() {
+        let channels = deinterleave(Cow::Borrowed(&[1, -1, 2, -2, 3, -3]), 2).unwrap();
+        assert_eq!(channels, vec![vec![1, 2, 3], vec![-1, -2, -3]]);
+        assert!(deinterleave(Cow::Borrowed(&[1, -1, 2]), 2).is_err());
+
+        let mono = deinterleave(Cow::Borrowed(&[1, 2, 3]), 1).unwrap();
+        assert!(matches!(mono.as_slice(), [Cow::Borrowed([1, 2, 3])]));
+    }
+
+    #[test]
+    fn test_apply_channel_mode() {
+        let channels = vec![Cow::Owned(vec![100, 100, 0, 0]), Cow::Owned(vec![-10, 20, 1000, -1000])];
+
+        let selected = apply_channel_mode(channels.clone(), ChannelMode::Select, 1, 1000).unwrap();
+        assert_eq!(selected, vec![vec![-10, 20, 1000, -1000]]);
+        assert!(apply_channel_mode(channels.clone(), ChannelMode::Select, 2, 1000).is_err());
+
+        let average = apply_channel_mode(channels.clone(), ChannelMode::DownmixAverage, 0, 1000).unwrap();
+        assert_eq!(average, vec![vec![45, 60, 500, -500]]);
+
+        // 20 ms frames of 2 samples at 100 Hz
+        let max_energy = apply_channel_mode(channels, ChannelMode::DownmixMaxEnergy, 0, 100).unwrap();
+        assert_eq!(max_energy, vec![vec![100, 100, 1000, -1000]]);
+
+        let mono = vec![Cow::Borrowed(&[1, 2, 3][..])];
+        let downmixed = apply_channel_mode(mono, ChannelMode::DownmixAverage, 0, 1000).unwrap();
+        assert!(matches!(downmixed.as_slice(), [Cow::Borrowed(_)]));
+    }
+}
@@ -0,0 +1,141 @@
+//! Decoding of compressed audio files with the pure-Rust symphonia decoders. Symphonia has no Opus decoder, Ogg Opus
+//! is decoded with libopus in `tools::opus`.
+
+use crate::pb::vad_grpc_v1::AudioType;
+use std::io::{Cursor, ErrorKind};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, Packet, Track};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use vad_grpc_server::VadServiceError;
+
+pub(crate) fn probe(audio: &[u8], extension: &str) -> vad_grpc_server::Result<Box<dyn FormatReader>> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(audio.to_vec())), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    Ok(probed.format)
+}
+
+pub(crate) fn audio_track(format: &dyn FormatReader) -> vad_grpc_server::Result<&Track> {
+    format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| VadServiceError::InvalidAudio("No audio track found".to_string()))
+}
+
+/// Returns the next packet of the track, or `None` at the end of the stream.
+pub(crate) fn next_packet(format: &mut dyn FormatReader, track_id: u32) -> vad_grpc_server::Result<Option<Packet>> {
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => return Ok(Some(packet)),
+            Ok(_) => continue,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Decodes a FLAC, Ogg Vorbis or MP3 file. Returns the interleaved samples, the number of channels and the sample
+/// rate of the file.
+pub fn decode(audio: &[u8], audio_type: AudioType) -> vad_grpc_server::Result<(Vec<i16>, usize, u32)> {
+    let extension = match audio_type {
+        AudioType::Flac => "flac",
+        AudioType::OggVorbis => "ogg",
+        AudioType::Mp3 => "mp3",
+        _ => {
+            return Err(VadServiceError::InvalidAudio(format!(
+                "{} audio can't be decoded with symphonia",
+                audio_type.as_str_name()
+            )))
+        }
+    };
+    let mut format = probe(audio, extension)?;
+    let track = audio_track(format.as_ref())?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples = Vec::new();
+    let mut spec = None;
+    while let Some(packet) = next_packet(format.as_mut(), track_id)? {
+        let decoded = decoder.decode(&packet)?;
+        spec = Some(*decoded.spec());
+        let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    let spec = spec.ok_or_else(|| VadServiceError::InvalidAudio("No audio in the stream".to_string()))?;
+    Ok((samples, spec.channels.count(), spec.rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |crc, &byte| {
+            (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 })
+        })
+    }
+
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |crc, &byte| {
+            (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+                if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x8005
+                } else {
+                    crc << 1
+                }
+            })
+        })
+    }
+
+    /// Mono 16-bit FLAC with a single verbatim frame.
+    fn verbatim_flac(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+        let block_size = samples.len() as u16;
+        let mut flac = b"fLaC".to_vec();
+        // last metadata block, STREAMINFO of 34 bytes
+        flac.extend_from_slice(&[0x80, 0, 0, 34]);
+        flac.extend_from_slice(&block_size.to_be_bytes());
+        flac.extend_from_slice(&block_size.to_be_bytes());
+        flac.extend_from_slice(&[0; 6]);
+        let info = (sample_rate as u64) << 44 | 15 << 36 | samples.len() as u64;
+        flac.extend_from_slice(&info.to_be_bytes());
+        flac.extend_from_slice(&[0; 16]);
+
+        let frame_start = flac.len();
+        // fixed blocking, 16-bit block size at the end of the header, sample rate from STREAMINFO, mono, 16 bits,
+        // frame number 0
+        flac.extend_from_slice(&[0xFF, 0xF8, 0x70, 0x08, 0x00]);
+        flac.extend_from_slice(&(block_size - 1).to_be_bytes());
+        flac.push(crc8(&flac[frame_start..]));
+        // verbatim subframe
+        flac.push(0x02);
+        samples.iter().for_each(|s| flac.extend_from_slice(&s.to_be_bytes()));
+        let crc = crc16(&flac[frame_start..]);
+        flac.extend_from_slice(&crc.to_be_bytes());
+        flac
+    }
+
+    #[test]
+    fn test_decode_flac() {
+        let samples = (0..1600).map(|i| ((i * 37) % 2000 - 1000) as i16).collect::<Vec<_>>();
+        let flac = verbatim_flac(&samples, 8000);
+
+        let (decoded, channels, sample_rate) = decode(&flac, AudioType::Flac).unwrap();
+        assert_eq!((channels, sample_rate), (1, 8000));
+        assert_eq!(decoded, samples);
+
+        assert!(decode(&flac[..100], AudioType::Flac).is_err());
+    }
+}
@@ -1,19 +1,214 @@
-use crate::pb::vad_grpc_v1::SpeechInterval;
-use silero_vad::TimeStamp;
+use crate::pb::vad_grpc_v1::{
+    AudioConfig, IntegerTimestamps, SpeechInterval, SpeechStats, SpeechSummary, TimeMapSegment,
+};
+use crate::tools::channels::DecodedAudio;
+use silero_vad::compact::TimeMap;
+use silero_vad::diarization::SpeakerTurn;
+use silero_vad::{TimeStamp, VadEvent};
+use std::ops::Range;
 
-pub fn timestamps_to_speech_intervals(timestamps: &[TimeStamp]) -> Vec<SpeechInterval> {
+/// Integer positions of the speech intervals requested by `AudioConfig.integer_timestamps`, computed from the sample
+/// indices of the VAD.
+#[derive(Debug, Clone, Copy)]
+pub struct IntegerPositions {
+    kind: IntegerTimestamps,
+    vad_sample_rate: u32,
+    input_sample_rate: u32,
+}
+
+impl Default for IntegerPositions {
+    fn default() -> Self {
+        Self {
+            kind: IntegerTimestamps::None,
+            vad_sample_rate: 0,
+            input_sample_rate: 0,
+        }
+    }
+}
+
+impl IntegerPositions {
+    pub fn new(config: &AudioConfig, decoded: &DecodedAudio) -> Self {
+        Self::with_rates(config, decoded.sample_rate, decoded.input_format.sample_rate)
+    }
+
+    pub fn with_rates(config: &AudioConfig, vad_sample_rate: u32, input_sample_rate: u32) -> Self {
+        Self {
+            kind: config.integer_timestamps(),
+            vad_sample_rate,
+            input_sample_rate,
+        }
+    }
+
+    fn apply(&self, interval: &mut SpeechInterval, samples: &Range<usize>) {
+        let (with_samples, with_ms) = match self.kind {
+            IntegerTimestamps::None => (false, false),
+            IntegerTimestamps::Samples => (true, false),
+            IntegerTimestamps::Milliseconds => (false, true),
+            IntegerTimestamps::SamplesAndMilliseconds => (true, true),
+        };
+        // the sample indices of the VAD are at the model rate, the input audio may have been resampled to it
+        let rescale = |sample: usize, rate: u32| rescale(sample as u64, rate, self.vad_sample_rate);
+        if with_samples {
+            interval.start_sample = Some(rescale(samples.start, self.input_sample_rate));
+            interval.end_sample = Some(rescale(samples.end, self.input_sample_rate));
+        }
+        if with_ms {
+            interval.start_ms = Some(rescale(samples.start, 1000));
+            interval.end_ms = Some(rescale(samples.end, 1000));
+        }
+    }
+}
+
+/// `value * numerator / denominator` rounded to the nearest integer.
+fn rescale(value: u64, numerator: u32, denominator: u32) -> u64 {
+    let denominator = denominator.max(1) as u64;
+    (value * numerator as u64 + denominator / 2) / denominator
+}
+
+pub fn timestamps_to_speech_intervals(timestamps: &[TimeStamp], positions: IntegerPositions) -> Vec<SpeechInterval> {
     timestamps
         .iter()
-        .map(|ts| SpeechInterval {
-            start_s: ts.start,
-            end_s: ts.end,
-        })
+        .map(|ts| timestamp_to_speech_interval(ts, positions))
         .collect()
 }
 
-pub fn timestamp_to_speech_interval(timestamp: &TimeStamp) -> SpeechInterval {
-    SpeechInterval {
+pub fn timestamp_to_speech_interval(timestamp: &TimeStamp, positions: IntegerPositions) -> SpeechInterval {
+    let mut interval = SpeechInterval {
         start_s: timestamp.start,
         end_s: timestamp.end,
+        stats: timestamp.stats.as_ref().map(speech_stats),
+        ..Default::default()
+    };
+    if let Some(samples) = &timestamp.samples {
+        positions.apply(&mut interval, samples);
+    }
+    interval
+}
+
+fn speech_stats(stats: &silero_vad::SpeechStats) -> SpeechStats {
+    SpeechStats {
+        mean_probability: stats.mean_probability,
+        max_probability: stats.max_probability,
+        frames: stats.frames as u32,
+        rms_dbfs: stats.rms_dbfs,
+    }
+}
+
+/// Speech intervals of every channel, tagged with the channel index for multi-channel audio and sorted by start.
+pub fn channel_timestamps_to_speech_intervals(
+    channels: &[Vec<TimeStamp>],
+    positions: IntegerPositions,
+) -> Vec<SpeechInterval> {
+    if let [timestamps] = channels {
+        return timestamps_to_speech_intervals(timestamps, positions);
+    }
+    let mut intervals = channels
+        .iter()
+        .enumerate()
+        .flat_map(|(channel, timestamps)| {
+            timestamps.iter().map(move |ts| SpeechInterval {
+                channel: Some(channel as u32),
+                ..timestamp_to_speech_interval(ts, positions)
+            })
+        })
+        .collect::<Vec<_>>();
+    intervals.sort_by(|a, b| a.start_s.total_cmp(&b.start_s).then(a.channel.cmp(&b.channel)));
+    intervals
+}
+
+/// Speech intervals of the speeches finished in the events of every channel of a stream, the speech starts being
+/// skipped, see `channel_timestamps_to_speech_intervals`.
+pub fn channel_events_to_speech_intervals(
+    channel_events: Vec<Vec<VadEvent>>,
+    positions: IntegerPositions,
+) -> Vec<SpeechInterval> {
+    let channels = channel_events
+        .into_iter()
+        .map(|events| {
+            events
+                .into_iter()
+                .filter_map(|event| match event {
+                    VadEvent::SpeechEnd(timestamp) => Some(timestamp),
+                    VadEvent::SpeechStart { .. } => None,
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    channel_timestamps_to_speech_intervals(&channels, positions)
+}
+
+/// Summary of the speech of any channel in `samples` samples per channel.
+pub fn channel_timestamps_to_summary(channels: &[Vec<TimeStamp>], samples: usize, sample_rate: u32) -> SpeechSummary {
+    let summary = silero_vad::SpeechSummary::new(&channels.concat(), samples, sample_rate as usize);
+    SpeechSummary {
+        audio_duration_s: summary.audio_duration,
+        speech_duration_s: summary.speech_duration,
+        speech_ratio: summary.speech_ratio,
+        segments: summary.segments as u32,
+        longest_silence_s: summary.longest_silence,
+        leading_silence_s: summary.leading_silence,
+        trailing_silence_s: summary.trailing_silence,
+    }
+}
+
+pub fn speaker_turns_to_speech_intervals(turns: &[SpeakerTurn]) -> Vec<SpeechInterval> {
+    turns
+        .iter()
+        .map(|turn| SpeechInterval {
+            start_s: turn.timestamp.start,
+            end_s: turn.timestamp.end,
+            speaker_id: Some(turn.speaker as u32),
+            ..Default::default()
+        })
+        .collect()
+}
+
+pub fn time_map_to_segments(time_map: &TimeMap, sample_rate: u32) -> Vec<TimeMapSegment> {
+    let to_seconds = |samples: usize| samples as f64 / sample_rate as f64;
+    time_map
+        .segments()
+        .iter()
+        .map(|segment| TimeMapSegment {
+            compacted_start_s: to_seconds(segment.compacted_start),
+            original_start_s: to_seconds(segment.original_start),
+            duration_s: to_seconds(segment.len),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::vad_grpc_v1::AudioFormat;
+
+    #[test]
+    fn test_integer_positions() {
+        let config = AudioConfig {
+            integer_timestamps: IntegerTimestamps::SamplesAndMilliseconds as i32,
+            ..Default::default()
+        };
+        // 44.1 kHz input resampled to 16 kHz for the VAD
+        let decoded = DecodedAudio {
+            channels: Vec::new(),
+            sample_rate: 16000,
+            input_rate_channels: None,
+            input_format: AudioFormat {
+                sample_rate: 44100,
+                ..Default::default()
+            },
+        };
+        let timestamp = TimeStamp {
+            start: 0.032,
+            end: 1.5,
+            samples: Some(512..24000),
+            stats: None,
+        };
+
+        let interval = timestamp_to_speech_interval(&timestamp, IntegerPositions::new(&config, &decoded));
+        assert_eq!((interval.start_sample, interval.end_sample), (Some(1411), Some(66150)));
+        assert_eq!((interval.start_ms, interval.end_ms), (Some(32), Some(1500)));
+
+        let interval = timestamp_to_speech_interval(&timestamp, IntegerPositions::default());
+        assert_eq!((interval.start_sample, interval.start_ms), (None, None));
     }
 }
@@ -0,0 +1,81 @@
+//! Serde helpers used by the generated protobuf types, so that the JSON representation follows the protobuf JSON
+//! mapping for `bytes` (base64) and enum (name string) fields.
+
+pub mod base64 {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+pub mod base64_list {
+    use base64::Engine;
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(list: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(list.len()))?;
+        for bytes in list {
+            seq.serialize_element(&base64::engine::general_purpose::STANDARD.encode(bytes))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|encoded| base64::engine::general_purpose::STANDARD.decode(encoded))
+            .collect::<Result<_, _>>()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Generates a serde module for an `i32` field holding a protobuf enum. The enum is written as its protobuf name and
+/// read either from its name or from its numeric value.
+macro_rules! proto_enum_serde {
+    ($module:ident, $enum:ty) => {
+        pub mod $module {
+            use serde::{Deserialize, Deserializer, Serializer};
+
+            pub fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+                match <$enum>::try_from(*value) {
+                    Ok(value) => serializer.serialize_str(value.as_str_name()),
+                    Err(_) => serializer.serialize_i32(*value),
+                }
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+                #[derive(Deserialize)]
+                #[serde(untagged)]
+                enum NameOrValue {
+                    Name(String),
+                    Value(i32),
+                }
+
+                match NameOrValue::deserialize(deserializer)? {
+                    NameOrValue::Name(name) => <$enum>::from_str_name(&name)
+                        .map(|value| value as i32)
+                        .or_else(|| name.parse().ok())
+                        .ok_or_else(|| serde::de::Error::custom(format!("unknown enum value: {}", name))),
+                    NameOrValue::Value(value) => Ok(value),
+                }
+            }
+        }
+    };
+}
+
+proto_enum_serde!(audio_type, crate::pb::vad_grpc_v1::AudioType);
+proto_enum_serde!(channel_mode, crate::pb::vad_grpc_v1::ChannelMode);
+proto_enum_serde!(speech_audio_mode, crate::pb::vad_grpc_v1::SpeechAudioMode);
+proto_enum_serde!(speech_audio_format, crate::pb::vad_grpc_v1::SpeechAudioFormat);
+proto_enum_serde!(vad_engine, crate::pb::vad_grpc_v1::VadEngine);
+proto_enum_serde!(integer_timestamps, crate::pb::vad_grpc_v1::IntegerTimestamps);
@@ -1,3 +1,12 @@
+pub(crate) mod channels;
+pub(crate) mod compressed;
 pub(crate) mod grpc;
+pub(crate) mod json;
+#[cfg(feature = "opus")]
+pub(crate) mod opus;
+pub(crate) mod pcm;
+pub(crate) mod resample;
+pub(crate) mod sniff;
+pub(crate) mod speech_audio;
 pub(crate) mod transcode;
 pub(crate) mod wav;
@@ -0,0 +1,196 @@
+//! Opus decoding with libopus, enabled by the `opus` feature.
+
+use crate::pb::vad_grpc_v1::OpusPacket;
+use crate::tools::compressed;
+use audiopus::coder::Decoder;
+use audiopus::packet::Packet;
+use audiopus::{Channels, MutSignals, SampleRate};
+use vad_grpc_server::VadServiceError;
+
+/// Rate at which Opus is decoded, libopus resamples internally.
+pub const OPUS_SAMPLE_RATE: u32 = 16000;
+/// Ogg Opus pre-skip is given at 48 kHz.
+const OGG_OPUS_RATE: usize = 48000;
+/// The longest Opus packet is 120 ms.
+const MAX_PACKET_SAMPLES: usize = OPUS_SAMPLE_RATE as usize * 120 / 1000;
+/// Gaps of more packets than this are treated as a discontinuity rather than concealed.
+const MAX_CONCEALED_PACKETS: u16 = 50;
+
+fn opus_error(e: audiopus::Error) -> VadServiceError {
+    VadServiceError::InvalidAudio(format!("Failed to decode Opus: {}", e))
+}
+
+pub struct OpusDecoder {
+    decoder: Decoder,
+    channels: usize,
+}
+
+impl OpusDecoder {
+    pub fn new(channels: usize) -> vad_grpc_server::Result<Self> {
+        let opus_channels = match channels {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            n => {
+                return Err(VadServiceError::InvalidAudio(format!("Opus streams of {} channels are not supported", n)))
+            }
+        };
+        let decoder = Decoder::new(SampleRate::Hz16000, opus_channels).map_err(opus_error)?;
+        Ok(Self { decoder, channels })
+    }
+
+    /// Decodes one packet into interleaved samples.
+    pub fn decode(&mut self, packet: &[u8]) -> vad_grpc_server::Result<Vec<i16>> {
+        let packet = Packet::try_from(packet).map_err(opus_error)?;
+        self.decode_packet(Some(packet), MAX_PACKET_SAMPLES)
+    }
+
+    /// Fills a gap of `samples` samples per channel with the packet loss concealment of the decoder.
+    pub fn conceal(&mut self, samples: usize) -> vad_grpc_server::Result<Vec<i16>> {
+        self.decode_packet(None, samples)
+    }
+
+    fn decode_packet(&mut self, packet: Option<Packet<'_>>, samples: usize) -> vad_grpc_server::Result<Vec<i16>> {
+        let mut output = vec![0i16; samples * self.channels];
+        let signals = MutSignals::try_from(&mut output).map_err(opus_error)?;
+        let decoded = self.decoder.decode(packet, signals, false).map_err(opus_error)?;
+        output.truncate(decoded * self.channels);
+        Ok(output)
+    }
+}
+
+/// Stateful decoder of the Opus packets of a stream, concealing lost packets.
+pub struct OpusPacketDecoder {
+    decoder: OpusDecoder,
+    last_sequence_number: Option<u16>,
+    last_packet_samples: usize,
+}
+
+impl OpusPacketDecoder {
+    pub fn new(channels: usize) -> vad_grpc_server::Result<Self> {
+        Ok(Self {
+            decoder: OpusDecoder::new(channels)?,
+            last_sequence_number: None,
+            // 20 ms, the most common packet duration
+            last_packet_samples: OPUS_SAMPLE_RATE as usize / 50,
+        })
+    }
+
+    /// Decodes the packets into interleaved samples, concealing empty packets and gaps in the sequence numbers.
+    pub fn decode(&mut self, packets: &[OpusPacket]) -> vad_grpc_server::Result<Vec<i16>> {
+        let mut samples = Vec::new();
+        for packet in packets {
+            // RTP sequence numbers are 16-bit and wrap around
+            let sequence_number = packet.sequence_number as u16;
+            let lost = match self.last_sequence_number {
+                Some(last) => sequence_number.wrapping_sub(last).wrapping_sub(1),
+                None => 0,
+            };
+            if lost >= u16::MAX / 2 {
+                // late or duplicated packet, its audio has already been concealed
+                continue;
+            }
+            if lost > MAX_CONCEALED_PACKETS {
+                log::debug!("Opus stream discontinuity of {} packets", lost);
+            } else {
+                for _ in 0..lost {
+                    samples.extend(self.decoder.conceal(self.last_packet_samples)?);
+                }
+            }
+            self.last_sequence_number = Some(sequence_number);
+
+            let decoded = if packet.data.is_empty() {
+                self.decoder.conceal(self.last_packet_samples)?
+            } else {
+                self.decoder.decode(&packet.data)?
+            };
+            self.last_packet_samples = decoded.len() / self.decoder.channels;
+            samples.extend(decoded);
+        }
+        Ok(samples)
+    }
+}
+
+/// Decodes an Ogg Opus file. Returns the interleaved samples, the number of channels and the sample rate.
+pub fn decode_ogg_opus(audio: &[u8]) -> vad_grpc_server::Result<(Vec<i16>, usize, u32)> {
+    let mut format = compressed::probe(audio, "ogg")?;
+    let track = compressed::audio_track(format.as_ref())?;
+    let track_id = track.id;
+    let channels = track.codec_params.channels.map_or(1, |channels| channels.count());
+    let pre_skip = track.codec_params.delay.unwrap_or(0) as usize * OPUS_SAMPLE_RATE as usize / OGG_OPUS_RATE;
+
+    let mut decoder = OpusDecoder::new(channels)?;
+    let mut samples = Vec::new();
+    while let Some(packet) = compressed::next_packet(format.as_mut(), track_id)? {
+        samples.extend(decoder.decode(&packet.data)?);
+    }
+    samples.drain(..(pre_skip * channels).min(samples.len()));
+    Ok((samples, channels, OPUS_SAMPLE_RATE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::vad_grpc_v1::AudioType;
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Channels, SampleRate};
+
+    fn ogg_crc(data: &[u8]) -> u32 {
+        data.iter().fold(0u32, |crc, &byte| {
+            (0..8).fold(crc ^ ((byte as u32) << 24), |crc, _| {
+                if crc & 0x8000_0000 != 0 {
+                    (crc << 1) ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                }
+            })
+        })
+    }
+
+    /// Ogg page holding a single packet.
+    fn ogg_page(header_type: u8, granule_position: u64, sequence_number: u32, packet: &[u8]) -> Vec<u8> {
+        let mut lacing = vec![255u8; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+        let mut page = b"OggS\0".to_vec();
+        page.push(header_type);
+        page.extend_from_slice(&granule_position.to_le_bytes());
+        page.extend_from_slice(&1u32.to_le_bytes());
+        page.extend_from_slice(&sequence_number.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(packet);
+        let crc = ogg_crc(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    #[test]
+    fn test_decode_ogg_opus() {
+        const PRE_SKIP: u16 = 312;
+        let encoder = Encoder::new(SampleRate::Hz16000, Channels::Mono, Application::Voip).unwrap();
+        let frame = (0..320)
+            .map(|i| ((i as f32 * 0.1).sin() * 8000.0) as i16)
+            .collect::<Vec<_>>();
+
+        let mut head = b"OpusHead\x01\x01".to_vec();
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&16000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        let mut ogg = ogg_page(0x02, 0, 0, &head);
+        ogg.extend(ogg_page(0, 0, 1, b"OpusTags\x04\0\0\0test\0\0\0\0"));
+        for i in 0..20u32 {
+            let mut packet = vec![0; 4000];
+            let len = encoder.encode(&frame, &mut packet).unwrap();
+            packet.truncate(len);
+            let header_type = if i == 19 { 0x04 } else { 0 };
+            let granule_position = (i as u64 + 1) * 960 + PRE_SKIP as u64;
+            ogg.extend(ogg_page(header_type, granule_position, i + 2, &packet));
+        }
+
+        let (samples, channels, sample_rate) = decode_ogg_opus(&ogg).unwrap();
+        assert_eq!((channels, sample_rate), (1, OPUS_SAMPLE_RATE));
+        assert_eq!(samples.len(), 20 * 320 - PRE_SKIP as usize / 3);
+        // symphonia has no Opus decoder
+        assert!(compressed::decode(&ogg, AudioType::OggOpus).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! Decoding and encoding of raw audio with an explicit byte order. Decoding validates that the audio is a whole
+//! number of samples instead of dropping the trailing bytes.
+
+use crate::pb::vad_grpc_v1::AudioType;
+use crate::tools::transcode::{
+    alaw_to_i16, f32_to_i16, i16_to_alaw, i16_to_f32, i16_to_mulaw, mulaw_to_i16, SampleFormat,
+};
+use std::borrow::Cow;
+use vad_grpc_server::VadServiceError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+/// Encoding of raw audio, i.e. audio without a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawEncoding {
+    Pcm(SampleFormat, ByteOrder),
+    Mulaw,
+    Alaw,
+}
+
+impl RawEncoding {
+    /// Returns the encoding of the `RAW_*` audio types, `None` for containers.
+    pub fn from_audio_type(audio_type: AudioType) -> Option<Self> {
+        use ByteOrder::*;
+        match audio_type {
+            AudioType::RawPcmS16le => Some(RawEncoding::Pcm(SampleFormat::S16, LittleEndian)),
+            AudioType::RawPcmS16be => Some(RawEncoding::Pcm(SampleFormat::S16, BigEndian)),
+            AudioType::RawPcmF32le => Some(RawEncoding::Pcm(SampleFormat::F32, LittleEndian)),
+            AudioType::RawPcmS24le => Some(RawEncoding::Pcm(SampleFormat::S24, LittleEndian)),
+            AudioType::RawPcmS32le => Some(RawEncoding::Pcm(SampleFormat::S32, LittleEndian)),
+            AudioType::RawPcmU8 => Some(RawEncoding::Pcm(SampleFormat::U8, LittleEndian)),
+            AudioType::RawMulaw => Some(RawEncoding::Mulaw),
+            AudioType::RawAlaw => Some(RawEncoding::Alaw),
+            _ => None,
+        }
+    }
+
+    /// Returns the encoding of the samples in the data chunk of the `WAV_*` audio types.
+    pub fn from_wav_audio_type(audio_type: AudioType) -> Option<Self> {
+        let format = match audio_type {
+            AudioType::WavPcmU8 => SampleFormat::U8,
+            AudioType::WavPcmS16le => SampleFormat::S16,
+            AudioType::WavPcmS24le => SampleFormat::S24,
+            AudioType::WavPcmS32le => SampleFormat::S32,
+            AudioType::WavPcmF32le => SampleFormat::F32,
+            _ => return None,
+        };
+        Some(RawEncoding::Pcm(format, ByteOrder::LittleEndian))
+    }
+
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            RawEncoding::Pcm(format, _) => format.bytes_per_sample(),
+            RawEncoding::Mulaw | RawEncoding::Alaw => 1,
+        }
+    }
+}
+
+/// Decodes raw audio into samples in the 16-bit scale. 16-bit little-endian audio is borrowed rather than copied when
+/// the host is little-endian and the buffer is aligned.
+pub fn decode(input: &[u8], encoding: RawEncoding) -> vad_grpc_server::Result<Cow<'_, [i16]>> {
+    let bytes_per_sample = encoding.bytes_per_sample();
+    if !input.len().is_multiple_of(bytes_per_sample) {
+        return Err(VadServiceError::InvalidAudio(format!(
+            "Raw audio of {} bytes is not a whole number of {}-byte samples",
+            input.len(),
+            bytes_per_sample
+        )));
+    }
+
+    if encoding == RawEncoding::Pcm(SampleFormat::S16, ByteOrder::LittleEndian) && cfg!(target_endian = "little") {
+        if let Ok(samples) = bytemuck::try_cast_slice::<u8, i16>(input) {
+            return Ok(Cow::Borrowed(samples));
+        }
+    }
+
+    let samples = match encoding {
+        RawEncoding::Pcm(format, byte_order) => input
+            .chunks_exact(bytes_per_sample)
+            .map(|bytes| decode_sample(bytes, format, byte_order))
+            .collect(),
+        RawEncoding::Mulaw => input.iter().map(|&b| mulaw_to_i16(b)).collect(),
+        RawEncoding::Alaw => input.iter().map(|&b| alaw_to_i16(b)).collect(),
+    };
+    Ok(Cow::Owned(samples))
+}
+
+/// Decodes one sample, `bytes` holding exactly `format.bytes_per_sample()` bytes.
+fn decode_sample(bytes: &[u8], format: SampleFormat, byte_order: ByteOrder) -> i16 {
+    // reorder the bytes to big-endian, the most significant byte first
+    let mut be = [0u8; 4];
+    be[..bytes.len()].copy_from_slice(bytes);
+    if byte_order == ByteOrder::LittleEndian {
+        be[..bytes.len()].reverse();
+    }
+    match format {
+        SampleFormat::U8 => ((be[0] as i16) - 128) << 8,
+        SampleFormat::S16 => i16::from_be_bytes([be[0], be[1]]),
+        SampleFormat::S24 | SampleFormat::S32 => (i32::from_be_bytes(be) >> 16) as i16,
+        SampleFormat::F32 => f32_to_i16(f32::from_be_bytes(be)),
+    }
+}
+
+/// Encodes samples as raw audio.
+pub fn encode(samples: &[i16], encoding: RawEncoding) -> Vec<u8> {
+    let mut output = Vec::with_capacity(samples.len() * encoding.bytes_per_sample());
+    for &sample in samples {
+        match encoding {
+            RawEncoding::Pcm(format, byte_order) => {
+                let be = match format {
+                    SampleFormat::U8 => [((sample >> 8) + 128) as u8, 0, 0, 0],
+                    SampleFormat::S16 | SampleFormat::S24 | SampleFormat::S32 => ((sample as i32) << 16).to_be_bytes(),
+                    SampleFormat::F32 => i16_to_f32(sample).to_be_bytes(),
+                };
+                let bytes = &be[..format.bytes_per_sample()];
+                match byte_order {
+                    ByteOrder::BigEndian => output.extend_from_slice(bytes),
+                    ByteOrder::LittleEndian => output.extend(bytes.iter().rev()),
+                }
+            }
+            RawEncoding::Mulaw => output.push(i16_to_mulaw(sample)),
+            RawEncoding::Alaw => output.push(i16_to_alaw(sample)),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const PCM_FORMATS: [SampleFormat; 5] = [
+        SampleFormat::U8,
+        SampleFormat::S16,
+        SampleFormat::S24,
+        SampleFormat::S32,
+        SampleFormat::F32,
+    ];
+
+    fn raw_encoding() -> impl Strategy<Value = RawEncoding> {
+        prop_oneof![
+            (
+                proptest::sample::select(PCM_FORMATS.to_vec()),
+                prop_oneof![Just(ByteOrder::LittleEndian), Just(ByteOrder::BigEndian)]
+            )
+                .prop_map(|(format, byte_order)| RawEncoding::Pcm(format, byte_order)),
+            Just(RawEncoding::Mulaw),
+            Just(RawEncoding::Alaw),
+        ]
+    }
+
+    #[test]
+    fn test_explicit_byte_order() {
+        let le = RawEncoding::Pcm(SampleFormat::S16, ByteOrder::LittleEndian);
+        let be = RawEncoding::Pcm(SampleFormat::S16, ByteOrder::BigEndian);
+        assert_eq!(decode(&[0x01, 0x02], le).unwrap().as_ref(), &[0x0201]);
+        assert_eq!(decode(&[0x01, 0x02], be).unwrap().as_ref(), &[0x0102]);
+        let s24 = RawEncoding::Pcm(SampleFormat::S24, ByteOrder::LittleEndian);
+        assert_eq!(decode(&[0x00, 0x34, 0x12], s24).unwrap().as_ref(), &[0x1234]);
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(samples in proptest::collection::vec(any::<i16>(), 0..64), encoding in raw_encoding()) {
+            let encoded = encode(&samples, encoding);
+            prop_assert_eq!(encoded.len(), samples.len() * encoding.bytes_per_sample());
+            let decoded = decode(&encoded, encoding).unwrap();
+            let tolerance = match encoding {
+                // 8-bit PCM keeps the most significant byte
+                RawEncoding::Pcm(SampleFormat::U8, _) => 255,
+                RawEncoding::Pcm(..) => 0,
+                // G.711 is lossy, up to 1024 for the largest mu-law segment and clipping above 32635
+                RawEncoding::Mulaw | RawEncoding::Alaw => 2048,
+            };
+            for (decoded, sample) in decoded.iter().zip(&samples) {
+                prop_assert!((*decoded as i32 - *sample as i32).abs() <= tolerance, "{} {}", decoded, sample);
+            }
+        }
+
+        #[test]
+        fn test_partial_samples_are_rejected(len in 0usize..64, encoding in raw_encoding()) {
+            let input = vec![0x55u8; len];
+            let result = decode(&input, encoding);
+            prop_assert_eq!(result.is_ok(), len % encoding.bytes_per_sample() == 0);
+            if let Ok(samples) = result {
+                prop_assert_eq!(samples.len(), len / encoding.bytes_per_sample());
+            }
+        }
+    }
+}
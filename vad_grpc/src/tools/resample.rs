@@ -0,0 +1,250 @@
+use rubato::{FftFixedInOut, Resampler};
+use vad_grpc_server::VadServiceError;
+
+const CHUNK_SIZE: usize = 1024;
+
+/// Sample rate the VAD runs at for audio of `sample_rate`: 8 kHz audio is analysed as is, everything else at 16 kHz.
+pub fn model_sample_rate(sample_rate: u32) -> u32 {
+    match sample_rate {
+        8000 => 8000,
+        _ => 16000,
+    }
+}
+
+/// Resamples every channel from `from` to `to` Hz.
+pub fn resample<C: AsRef<[i16]>>(channels: &[C], from: u32, to: u32) -> vad_grpc_server::Result<Vec<Vec<i16>>> {
+    channels
+        .iter()
+        .map(|channel| {
+            let mut resampler = StreamResampler::new(from, to)?;
+            let mut output = resampler.push(channel.as_ref())?;
+            output.extend(resampler.finish()?);
+            Ok(output)
+        })
+        .collect()
+}
+
+/// Resampler of one channel of a stream.
+///
+/// The chunks are resampled as if the whole stream was resampled at once: the filter state is kept between chunks,
+/// and the samples that don't fill a chunk of the resampler wait for the next one. The output is delayed by up to
+/// a chunk of the resampler, `finish` returns the rest.
+pub struct StreamResampler {
+    from: u32,
+    to: u32,
+    /// `None` when the rates are equal and the samples pass through.
+    resampler: Option<FftFixedInOut<f32>>,
+    pending: Vec<f32>,
+    /// Last chunk passed to the resampler, whose filter state depends only on it.
+    last_chunk: Vec<f32>,
+    /// Output samples still to drop for the delay of the resampler.
+    to_skip: usize,
+    consumed: u64,
+    produced: u64,
+}
+
+impl StreamResampler {
+    pub fn new(from: u32, to: u32) -> vad_grpc_server::Result<Self> {
+        let resampler = (from != to)
+            .then(|| FftFixedInOut::<f32>::new(from as usize, to as usize, chunk_size(from, to), 1))
+            .transpose()
+            .map_err(|e| resample_error(&e))?;
+        Ok(Self {
+            from,
+            to,
+            to_skip: resampler.as_ref().map_or(0, |resampler| resampler.output_delay()),
+            resampler,
+            pending: Vec::new(),
+            last_chunk: Vec::new(),
+            consumed: 0,
+            produced: 0,
+        })
+    }
+
+    /// Pushes the next samples and returns the resampled samples available so far.
+    pub fn push(&mut self, samples: &[i16]) -> vad_grpc_server::Result<Vec<i16>> {
+        self.consumed += samples.len() as u64;
+        let Some(resampler) = &mut self.resampler else {
+            self.produced += samples.len() as u64;
+            return Ok(samples.to_vec());
+        };
+        self.pending.extend(samples.iter().map(|&x| x as f32));
+
+        let chunk_len = resampler.input_frames_next();
+        let mut output = Vec::new();
+        let mut position = 0;
+        while position + chunk_len <= self.pending.len() {
+            let chunk = &self.pending[position..position + chunk_len];
+            output.extend(
+                resampler
+                    .process(&[chunk], None)
+                    .map_err(|e| resample_error(&e))?
+                    .remove(0),
+            );
+            position += chunk_len;
+        }
+        if position > 0 {
+            self.last_chunk = self.pending[position - chunk_len..position].to_vec();
+            self.pending.drain(..position);
+        }
+        Ok(self.emit(output))
+    }
+
+    /// Saves the state of the resampler, to continue the stream with `resume`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        for value in [
+            self.to_skip as u64,
+            self.consumed,
+            self.produced,
+            self.last_chunk.len() as u64,
+        ] {
+            state.extend_from_slice(&value.to_le_bytes());
+        }
+        // the samples came from 16-bit audio
+        for &sample in self.last_chunk.iter().chain(&self.pending) {
+            state.extend_from_slice(&(sample as i16).to_le_bytes());
+        }
+        state
+    }
+
+    /// Resumes a stream from the state saved by `save_state`. The filter state is restored by passing the last chunk
+    /// to the resampler again.
+    pub fn resume(from: u32, to: u32, state: &[u8]) -> vad_grpc_server::Result<Self> {
+        let invalid = || VadServiceError::InvalidAudio("Invalid resampler state".to_string());
+        let (header, samples) = state.split_first_chunk::<32>().ok_or_else(invalid)?;
+        let field = |i: usize| u64::from_le_bytes(header[i * 8..(i + 1) * 8].try_into().expect("8 bytes"));
+        if !samples.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let samples = samples
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32)
+            .collect::<Vec<_>>();
+        let last_chunk_len = field(3) as usize;
+        if last_chunk_len > samples.len() {
+            return Err(invalid());
+        }
+
+        let mut resampler = Self::new(from, to)?;
+        if let (Some(inner), false) = (&mut resampler.resampler, last_chunk_len == 0) {
+            if last_chunk_len != inner.input_frames_next() {
+                return Err(invalid());
+            }
+            inner
+                .process(&[&samples[..last_chunk_len]], None)
+                .map_err(|e| resample_error(&e))?;
+        }
+        resampler.to_skip = field(0) as usize;
+        resampler.consumed = field(1);
+        resampler.produced = field(2);
+        resampler.last_chunk = samples[..last_chunk_len].to_vec();
+        resampler.pending = samples[last_chunk_len..].to_vec();
+        Ok(resampler)
+    }
+
+    /// Resamples the pending samples and flushes the samples delayed by the resampler.
+    pub fn finish(mut self) -> vad_grpc_server::Result<Vec<i16>> {
+        let Some(mut resampler) = self.resampler.take() else {
+            return Ok(Vec::new());
+        };
+        let expected = self.consumed * self.to as u64 / self.from as u64;
+        let mut output = Vec::new();
+        if !self.pending.is_empty() {
+            let chunk = resampler
+                .process_partial(Some(&[&self.pending]), None)
+                .map_err(|e| resample_error(&e))?
+                .remove(0);
+            output.extend(self.emit(chunk));
+        }
+        while self.produced < expected {
+            let chunk = resampler
+                .process_partial::<&[f32]>(None, None)
+                .map_err(|e| resample_error(&e))?
+                .remove(0);
+            output.extend(self.emit(chunk));
+        }
+        let excess = (self.produced - expected) as usize;
+        output.truncate(output.len().saturating_sub(excess));
+        Ok(output)
+    }
+
+    /// Drops the delay of the resampler from the start of the output and converts it to 16-bit samples.
+    fn emit(&mut self, output: Vec<f32>) -> Vec<i16> {
+        let skip = self.to_skip.min(output.len());
+        self.to_skip -= skip;
+        self.produced += (output.len() - skip) as u64;
+        output[skip..]
+            .iter()
+            .map(|&x| x.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+            .collect()
+    }
+}
+
+/// Input chunk size of the resampler: about `CHUNK_SIZE`, and an even number of the smallest chunks the rates allow,
+/// as an odd chunk would delay the output by half a sample more than `output_delay` reports.
+fn chunk_size(from: u32, to: u32) -> usize {
+    let (mut a, mut b) = (from, to);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    let unit = (from / a) as usize;
+    CHUNK_SIZE.div_ceil(unit).next_multiple_of(2) * unit
+}
+
+fn resample_error(e: &dyn std::error::Error) -> VadServiceError {
+    VadServiceError::InvalidAudio(format!("Failed to resample: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resample_keeps_duration_and_tone() {
+        let from = 44100;
+        let tone = |rate: u32, i: usize| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / rate as f32).sin() * 10000.0;
+        let samples = (0..from as usize).map(|i| tone(from, i) as i16).collect::<Vec<_>>();
+
+        let resampled = resample(&[samples.clone(), samples], from, 16000).unwrap();
+
+        assert_eq!(resampled.len(), 2);
+        assert_eq!(resampled[0].len(), 16000);
+        let max_error = resampled[0][100..15900]
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| (x as f32 - tone(16000, i + 100)).abs())
+            .fold(0.0, f32::max);
+        assert!(max_error < 200.0, "max error {}", max_error);
+    }
+
+    #[test]
+    fn test_stream_resampler_matches_whole_audio() {
+        let samples = (0..30000)
+            .map(|i| ((i * 7919) % 20000) as i16 - 10000)
+            .collect::<Vec<_>>();
+        let whole = resample(&[&samples], 44100, 16000).unwrap().remove(0);
+
+        let mut resampler = StreamResampler::new(44100, 16000).unwrap();
+        let mut chunked = Vec::new();
+        for chunk in samples.chunks(317) {
+            chunked.extend(resampler.push(chunk).unwrap());
+        }
+        chunked.extend(resampler.finish().unwrap());
+        assert_eq!(chunked.len(), 30000 * 16000 / 44100);
+        assert_eq!(chunked, whole);
+
+        // a resampler resumed in the middle of the stream continues it exactly
+        let mut resampler = StreamResampler::new(44100, 16000).unwrap();
+        let mut resumed = resampler.push(&samples[..10000]).unwrap();
+        let mut resampler = StreamResampler::resume(44100, 16000, &resampler.save_state()).unwrap();
+        resumed.extend(resampler.push(&samples[10000..]).unwrap());
+        resumed.extend(resampler.finish().unwrap());
+        assert_eq!(resumed, whole);
+        assert!(StreamResampler::resume(44100, 16000, &[0; 10]).is_err());
+
+        let mut passthrough = StreamResampler::new(16000, 16000).unwrap();
+        assert_eq!(passthrough.push(&samples[..100]).unwrap(), &samples[..100]);
+        assert!(passthrough.finish().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+//! Detection of the audio format from the magic bytes of the container, used for `AudioType::Auto`.
+
+use crate::pb::vad_grpc_v1::AudioType;
+use crate::tools::wav;
+
+/// Returns the type of the audio file, or `None` if it isn't a known container, e.g. raw PCM.
+pub fn detect_audio_type(audio: &[u8]) -> Option<AudioType> {
+    match audio {
+        // the sample format of WAV audio is read from its header
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => {
+            Some(wav::wav_audio_type(audio).unwrap_or(AudioType::WavPcmS16le))
+        }
+        [b'f', b'L', b'a', b'C', ..] => Some(AudioType::Flac),
+        [b'O', b'g', b'g', b'S', ..] => ogg_audio_type(audio),
+        [b'I', b'D', b'3', ..] => Some(AudioType::Mp3),
+        // MPEG audio frame sync, the layer bits are never 00
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => Some(AudioType::Mp3),
+        _ => None,
+    }
+}
+
+/// Tells the codec of an Ogg stream from the identification header in its first page.
+fn ogg_audio_type(audio: &[u8]) -> Option<AudioType> {
+    // the first page carries a single segment, its header is 27 bytes plus the segment table
+    let segments = *audio.get(26)? as usize;
+    let packet = audio.get(27 + segments..)?;
+    if packet.starts_with(b"OpusHead") {
+        Some(AudioType::OggOpus)
+    } else if packet.starts_with(b"\x01vorbis") {
+        Some(AudioType::OggVorbis)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::transcode::SampleFormat;
+
+    fn ogg_page(packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0; 22]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    #[test]
+    fn test_detect_audio_type() {
+        let samples = vec![0i16; 160];
+        let wav = wav::samples_to_wav(&samples, 8000, SampleFormat::S16).unwrap();
+        assert_eq!(detect_audio_type(&wav), Some(AudioType::WavPcmS16le));
+        let wav = wav::samples_to_wav(&samples, 8000, SampleFormat::F32).unwrap();
+        assert_eq!(detect_audio_type(&wav), Some(AudioType::WavPcmF32le));
+
+        assert_eq!(detect_audio_type(b"fLaC\0\0\0\x22"), Some(AudioType::Flac));
+        assert_eq!(detect_audio_type(&ogg_page(b"OpusHead\x01\x01")), Some(AudioType::OggOpus));
+        assert_eq!(detect_audio_type(&ogg_page(b"\x01vorbis\0\0")), Some(AudioType::OggVorbis));
+        assert_eq!(detect_audio_type(&ogg_page(b"Speex   ")), None);
+        assert_eq!(detect_audio_type(b"ID3\x04\0\0"), Some(AudioType::Mp3));
+        assert_eq!(detect_audio_type(&[0xFF, 0xFB, 0x90, 0x64]), Some(AudioType::Mp3));
+
+        // raw PCM can't be told apart from noise
+        assert_eq!(detect_audio_type(&[0x00, 0x01, 0xFF, 0x7F]), None);
+        assert_eq!(detect_audio_type(&[]), None);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::pb::vad_grpc_v1::{
+    AudioType, SpeechAudio, SpeechAudioFormat, SpeechAudioMapping, SpeechAudioMode, SpeechAudioOptions,
+};
+use crate::tools::transcode::{encode_samples, output_audio_type};
+use silero_vad::TimeStamp;
+use std::ops::Range;
+
+/// Cuts the speech out of the decoded `samples` according to `timestamps` and encodes it as requested in `options`.
+/// Returns `None` if no speech audio was requested.
+pub fn speech_audio(
+    samples: &[i16],
+    sample_rate: u32,
+    timestamps: &[TimeStamp],
+    input_type: AudioType,
+    options: &SpeechAudioOptions,
+) -> vad_grpc_server::Result<Option<SpeechAudio>> {
+    let audio_type = match options.format() {
+        SpeechAudioFormat::SameAsInput => output_audio_type(input_type),
+        SpeechAudioFormat::Wav => AudioType::WavPcmS16le,
+    };
+    let mut speech_audio = SpeechAudio {
+        audio_type: audio_type as i32,
+        ..Default::default()
+    };
+
+    match options.mode() {
+        SpeechAudioMode::None => return Ok(None),
+        SpeechAudioMode::PerInterval => {
+            speech_audio.segments = timestamps
+                .iter()
+                .map(|ts| {
+                    encode_samples(&samples[sample_range(ts, sample_rate, samples.len())], sample_rate, audio_type)
+                })
+                .collect::<vad_grpc_server::Result<_>>()?;
+        }
+        SpeechAudioMode::Concatenated => {
+            let mut speech = Vec::new();
+            for ts in timestamps {
+                speech_audio.mapping.push(SpeechAudioMapping {
+                    offset_s: speech.len() as f64 / sample_rate as f64,
+                    start_s: ts.start,
+                    end_s: ts.end,
+                });
+                speech.extend_from_slice(&samples[sample_range(ts, sample_rate, samples.len())]);
+            }
+            speech_audio.concatenated = encode_samples(&speech, sample_rate, audio_type)?;
+        }
+    }
+    Ok(Some(speech_audio))
+}
+
+fn sample_range(timestamp: &TimeStamp, sample_rate: u32, samples_len: usize) -> Range<usize> {
+    let to_sample = |seconds: f64| ((seconds * sample_rate as f64).round() as usize).min(samples_len);
+    let start = to_sample(timestamp.start);
+    start..to_sample(timestamp.end).max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concatenated_speech_audio() {
+        let samples: Vec<i16> = (0..8000).collect();
+        let timestamps = vec![
+            TimeStamp {
+                start: 0.1,
+                end: 0.2,
+                samples: None,
+                stats: None,
+            },
+            TimeStamp {
+                start: 0.5,
+                end: 0.75,
+                samples: None,
+                stats: None,
+            },
+        ];
+        let options = SpeechAudioOptions {
+            mode: SpeechAudioMode::Concatenated as i32,
+            format: SpeechAudioFormat::SameAsInput as i32,
+        };
+
+        let speech_audio = speech_audio(&samples, 8000, &timestamps, AudioType::RawPcmS16le, &options)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(speech_audio.concatenated.len(), (800 + 2000) * 2);
+        assert_eq!(&speech_audio.concatenated[..2], &800i16.to_le_bytes());
+        assert_eq!(&speech_audio.concatenated[1600..1602], &4000i16.to_le_bytes());
+        assert_eq!(speech_audio.mapping.len(), 2);
+        assert_eq!(speech_audio.mapping[1].offset_s, 0.1);
+        assert_eq!(speech_audio.mapping[1].start_s, 0.5);
+    }
+
+    #[test]
+    fn test_wav_speech_audio_at_input_rate() {
+        let samples = vec![1000i16; 44100];
+        let timestamps = vec![TimeStamp {
+            start: 0.1,
+            end: 0.3,
+            ..Default::default()
+        }];
+        let options = SpeechAudioOptions {
+            mode: SpeechAudioMode::PerInterval as i32,
+            format: SpeechAudioFormat::Wav as i32,
+        };
+
+        let speech_audio = speech_audio(&samples, 44100, &timestamps, AudioType::RawPcmS16le, &options)
+            .unwrap()
+            .unwrap();
+
+        let reader = hound::WavReader::new(speech_audio.segments[0].as_slice()).unwrap();
+        assert_eq!(reader.spec().sample_rate, 44100);
+        assert_eq!(reader.len(), 8820);
+    }
+}
@@ -1,10 +1,147 @@
-pub fn pcm_s16be_to_pcm_s16le(input: &[u8]) -> Vec<u8> {
-    let mut output = Vec::new();
-    for i in 0..input.len() {
-        if i % 2 == 0 {
-            output.push(input[i + 1]);
-            output.push(input[i]);
+use crate::pb::vad_grpc_v1::AudioType;
+use crate::tools::pcm::{self, RawEncoding};
+use crate::tools::wav;
+
+/// Sample encodings of PCM audio, the byte order is given separately. Samples are converted to and from the 16-bit
+/// scale the VAD works with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    S16,
+    S24,
+    S32,
+    F32,
+}
+
+impl SampleFormat {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16 => 2,
+            SampleFormat::S24 => 3,
+            SampleFormat::S32 | SampleFormat::F32 => 4,
+        }
+    }
+}
+
+/// Full scale of 16-bit samples, float samples are in `[-1.0, 1.0)`.
+const I16_SCALE: f32 = 32768.0;
+
+pub fn f32_to_i16(sample: f32) -> i16 {
+    (sample * I16_SCALE).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+pub fn i16_to_f32(sample: i16) -> f32 {
+    sample as f32 / I16_SCALE
+}
+
+const MULAW_BIAS: i32 = 0x84;
+const MULAW_CLIP: i32 = 32635;
+
+/// Decodes a G.711 mu-law byte.
+pub fn mulaw_to_i16(byte: u8) -> i16 {
+    let byte = !byte;
+    let exponent = (byte >> 4) & 0x07;
+    let mantissa = (byte & 0x0F) as i32;
+    let magnitude = (((mantissa << 3) + MULAW_BIAS) << exponent) - MULAW_BIAS;
+    if byte & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// Encodes a sample as a G.711 mu-law byte.
+pub fn i16_to_mulaw(sample: i16) -> u8 {
+    let sign = if sample < 0 { 0x80 } else { 0 };
+    let magnitude = (sample as i32).abs().min(MULAW_CLIP) + MULAW_BIAS;
+    let exponent = (31 - magnitude.leading_zeros() as i32 - 7).clamp(0, 7);
+    let mantissa = (magnitude >> (exponent + 3)) & 0x0F;
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+/// Decodes a G.711 A-law byte.
+pub fn alaw_to_i16(byte: u8) -> i16 {
+    let byte = byte ^ 0x55;
+    let exponent = (byte >> 4) & 0x07;
+    let mantissa = (byte & 0x0F) as i16;
+    let magnitude = match exponent {
+        0 => (mantissa << 4) + 8,
+        _ => ((mantissa << 4) + 0x108) << (exponent - 1),
+    };
+    // the sign bit is set for positive samples
+    if byte & 0x80 != 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+/// Encodes a sample as a G.711 A-law byte.
+pub fn i16_to_alaw(sample: i16) -> u8 {
+    let sign = if sample >= 0 { 0x80 } else { 0 };
+    let magnitude = (sample as i32).abs().min(i16::MAX as i32);
+    let (exponent, mantissa) = if magnitude < 256 {
+        (0, magnitude >> 4)
+    } else {
+        let exponent = 31 - magnitude.leading_zeros() as i32 - 7;
+        (exponent, (magnitude >> (exponent + 3)) & 0x0F)
+    };
+    (sign | (exponent << 4) as u8 | mantissa as u8) ^ 0x55
+}
+
+/// Encoding of the audio returned for `input_type` input: compressed formats are returned as WAV.
+pub fn output_audio_type(input_type: AudioType) -> AudioType {
+    match input_type {
+        AudioType::Unspecified
+        | AudioType::Auto
+        | AudioType::Flac
+        | AudioType::OggVorbis
+        | AudioType::OggOpus
+        | AudioType::OpusPackets
+        | AudioType::Mp3 => AudioType::WavPcmS16le,
+        audio_type => audio_type,
+    }
+}
+
+/// Encodes mono samples as `audio_type`, falling back to WAV if `audio_type` doesn't specify a PCM encoding.
+pub fn encode_samples(samples: &[i16], sample_rate: u32, audio_type: AudioType) -> vad_grpc_server::Result<Vec<u8>> {
+    if let Some(encoding) = RawEncoding::from_audio_type(audio_type) {
+        return Ok(pcm::encode(samples, encoding));
+    }
+    let format = match audio_type {
+        AudioType::WavPcmF32le => SampleFormat::F32,
+        AudioType::WavPcmS24le => SampleFormat::S24,
+        AudioType::WavPcmS32le => SampleFormat::S32,
+        AudioType::WavPcmU8 => SampleFormat::U8,
+        // 16-bit WAV, also returned for compressed input
+        _ => SampleFormat::S16,
+    };
+    wav::samples_to_wav(samples, sample_rate, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_g711() {
+        assert_eq!(mulaw_to_i16(0xFF), 0);
+        assert_eq!(mulaw_to_i16(0x00), -32124);
+        assert_eq!(mulaw_to_i16(0x80), 32124);
+        assert_eq!(alaw_to_i16(0xD5), 8);
+        assert_eq!(alaw_to_i16(0x55), -8);
+        assert_eq!(alaw_to_i16(0xAA), 32256);
+
+        for byte in 0..=u8::MAX {
+            assert_eq!(i16_to_mulaw(mulaw_to_i16(byte)), if byte == 0x7F { 0xFF } else { byte });
+            assert_eq!(i16_to_alaw(alaw_to_i16(byte)), byte);
+        }
+        for sample in (i16::MIN..=i16::MAX).step_by(7) {
+            let error = (mulaw_to_i16(i16_to_mulaw(sample)) as i32 - sample as i32).abs();
+            assert!(error <= 1024 || sample.unsigned_abs() > 32000, "mu-law {} {}", sample, error);
+            let error = (alaw_to_i16(i16_to_alaw(sample)) as i32 - sample as i32).abs();
+            assert!(error <= 512, "A-law {} {}", sample, error);
         }
     }
-    output
 }
@@ -1,16 +1,170 @@
-pub fn get_samples_from_wav(wav: &[u8]) -> vad_grpc_server::Result<Vec<i16>> {
+use crate::pb::vad_grpc_v1::{AudioFormat, AudioType};
+use crate::tools::transcode::{f32_to_i16, i16_to_f32, SampleFormat};
+use vad_grpc_server::VadServiceError;
+
+/// Returns the `WAV_*` audio type matching the sample format in the header of the WAV file.
+pub fn wav_audio_type(wav: &[u8]) -> Option<AudioType> {
+    let spec = hound::WavReader::new(wav).ok()?.spec();
+    match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 8) => Some(AudioType::WavPcmU8),
+        (hound::SampleFormat::Int, 16) => Some(AudioType::WavPcmS16le),
+        (hound::SampleFormat::Int, 24) => Some(AudioType::WavPcmS24le),
+        (hound::SampleFormat::Int, 32) => Some(AudioType::WavPcmS32le),
+        (hound::SampleFormat::Float, 32) => Some(AudioType::WavPcmF32le),
+        _ => None,
+    }
+}
+
+/// Checks that the sample format in the header of the WAV file is the one declared by `audio_type`. Headers that
+/// can't be read are left to `get_samples_from_wav` to report.
+pub fn check_wav_audio_type(wav: &[u8], audio_type: AudioType) -> vad_grpc_server::Result<()> {
+    match wav_audio_type(wav) {
+        Some(header_type) if header_type != audio_type => Err(VadServiceError::InvalidAudio(format!(
+            "Audio type {} doesn't match the WAV header, which is {}",
+            audio_type.as_str_name(),
+            header_type.as_str_name()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the header of a WAV stream and returns its format with the offset of the samples. The data chunk may be
+/// truncated, as the samples of a stream continue in the following chunks.
+pub fn read_stream_header(wav: &[u8]) -> vad_grpc_server::Result<(AudioFormat, usize)> {
+    let reader = hound::WavReader::new(std::io::Cursor::new(wav))?;
+    let spec = reader.spec();
+    let audio_type = wav_audio_type(wav).ok_or_else(|| {
+        VadServiceError::InvalidAudio(format!(
+            "Unsupported WAV sample format: {:?} {} bits",
+            spec.sample_format, spec.bits_per_sample
+        ))
+    })?;
+    let format = AudioFormat {
+        audio_type: audio_type as i32,
+        sample_rate: spec.sample_rate,
+        channels: spec.channels as u32,
+    };
+    Ok((format, reader.into_inner().position() as usize))
+}
+
+/// Returns the interleaved samples, converted to the 16-bit scale, the number of channels and the sample rate of the
+/// WAV file.
+pub fn get_samples_from_wav(wav: &[u8]) -> vad_grpc_server::Result<(Vec<i16>, usize, u32)> {
     let mut reader = hound::WavReader::new(wav)?;
-    let samples: Vec<i16> = reader.samples().filter_map(|s| s.ok()).collect();
-    Ok(samples)
+    let spec = reader.spec();
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        // hound converts unsigned 8-bit samples to i8
+        (hound::SampleFormat::Int, 8) => reader
+            .samples::<i8>()
+            .map(|s| s.map(|s| (s as i16) << 8))
+            .collect::<Result<_, _>>()?,
+        (hound::SampleFormat::Int, 16) => reader.samples::<i16>().collect::<Result<_, _>>()?,
+        (hound::SampleFormat::Int, bits @ (24 | 32)) => reader
+            .samples::<i32>()
+            .map(|s| s.map(|s| (s >> (bits - 16)) as i16))
+            .collect::<Result<_, _>>()?,
+        (hound::SampleFormat::Float, 32) => reader
+            .samples::<f32>()
+            .map(|s| s.map(f32_to_i16))
+            .collect::<Result<_, _>>()?,
+        (format, bits) => {
+            return Err(VadServiceError::InvalidAudio(format!(
+                "Unsupported WAV sample format: {:?} {} bits",
+                format, bits
+            )))
+        }
+    };
+    Ok((samples, spec.channels as usize, spec.sample_rate))
 }
 
-pub fn bytes_to_i16(bytes: &[u8]) -> Vec<i16> {
-    bytes
-        .chunks_exact(2)
-        .map(|chunk| {
-            let mut bytes = [0; 2];
-            bytes.copy_from_slice(chunk);
-            i16::from_ne_bytes(bytes)
-        })
-        .collect()
+pub fn samples_to_wav(samples: &[i16], sample_rate: u32, format: SampleFormat) -> vad_grpc_server::Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: format.bytes_per_sample() as u16 * 8,
+        sample_format: match format {
+            SampleFormat::F32 => hound::SampleFormat::Float,
+            _ => hound::SampleFormat::Int,
+        },
+    };
+    let mut wav = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut wav, spec)?;
+    for &sample in samples {
+        match format {
+            SampleFormat::U8 => writer.write_sample((sample >> 8) as i8)?,
+            SampleFormat::S16 => writer.write_sample(sample)?,
+            SampleFormat::S24 => writer.write_sample((sample as i32) << 8)?,
+            SampleFormat::S32 => writer.write_sample((sample as i32) << 16)?,
+            SampleFormat::F32 => writer.write_sample(i16_to_f32(sample))?,
+        }
+    }
+    writer.finalize()?;
+    Ok(wav.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::pcm::RawEncoding;
+    use proptest::prelude::*;
+
+    const WAV_FORMATS: [SampleFormat; 5] = [
+        SampleFormat::U8,
+        SampleFormat::S16,
+        SampleFormat::S24,
+        SampleFormat::S32,
+        SampleFormat::F32,
+    ];
+
+    #[test]
+    fn test_wav_round_trip() {
+        let samples = vec![0, 256, -256, 12800, -12800, i16::MIN];
+        for format in [
+            SampleFormat::U8,
+            SampleFormat::S16,
+            SampleFormat::S24,
+            SampleFormat::S32,
+            SampleFormat::F32,
+        ] {
+            let wav = samples_to_wav(&samples, 16000, format).unwrap();
+            assert_eq!(get_samples_from_wav(&wav).unwrap(), (samples.clone(), 1, 16000), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_check_wav_audio_type() {
+        let wav = samples_to_wav(&[1, 2, 3, 4], 16000, SampleFormat::S24).unwrap();
+        assert!(check_wav_audio_type(&wav, AudioType::WavPcmS24le).is_ok());
+        assert!(check_wav_audio_type(&wav, AudioType::WavPcmS16le).is_err());
+    }
+
+    #[test]
+    fn test_truncated_wav_is_reported() {
+        let wav = samples_to_wav(&[1, 2, 3, 4], 16000, SampleFormat::S24).unwrap();
+        assert!(get_samples_from_wav(&wav[..wav.len() - 1]).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_wav_round_trip_any_samples(
+            samples in proptest::collection::vec(any::<i16>(), 0..64),
+            format in proptest::sample::select(WAV_FORMATS.to_vec()),
+        ) {
+            let wav = samples_to_wav(&samples, 16000, format).unwrap();
+            let (decoded, channels, sample_rate) = get_samples_from_wav(&wav).unwrap();
+            prop_assert_eq!((channels, sample_rate), (1, 16000));
+            // 8-bit WAV keeps the most significant byte
+            let tolerance = if format == SampleFormat::U8 { 255 } else { 0 };
+            prop_assert_eq!(decoded.len(), samples.len());
+            for (decoded, sample) in decoded.iter().zip(&samples) {
+                prop_assert!((*decoded as i32 - *sample as i32).abs() <= tolerance, "{} {}", decoded, sample);
+            }
+
+            // streams read the header once and decode the rest as raw PCM
+            let (stream_format, data_offset) = read_stream_header(&wav).unwrap();
+            let encoding = RawEncoding::from_wav_audio_type(stream_format.audio_type()).unwrap();
+            let raw = crate::tools::pcm::decode(&wav[data_offset..], encoding).unwrap();
+            prop_assert_eq!(raw.as_ref(), decoded.as_slice());
+        }
+    }
 }